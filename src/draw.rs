@@ -0,0 +1,233 @@
+use crate::geometry::{Triangle, Vertex};
+use crate::image::Image;
+use crate::image::picking::PrimitiveId;
+use crate::image::pixel::Pixel;
+use crate::math::matrix::{Matrix4d, mul_vector};
+use crate::math::vector::{Vec3d, Vec4d};
+
+/// Triangle of a draw call being shaded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Primitive {
+    /// Index into the draw call's instances.
+    pub instance: usize,
+    /// Index into the index buffer.
+    pub triangle: usize,
+    /// Indices of its vertices in the vertex buffer, for looking up their attributes.
+    pub vertices: [usize; 3],
+}
+
+/// Indexed mesh drawn once per instance.
+pub struct DrawCall<'a> {
+    pub vertices: &'a [Vertex],
+    /// Three indices into `vertices` per triangle.
+    pub indices: &'a [Triangle],
+    /// Transform from model to clip space of each copy of the mesh.
+    pub instances: &'a [Matrix4d],
+}
+
+/// Clip-space positions of the vertices of one instance, transformed when first referenced.
+struct VertexCache {
+    clip: Vec<Option<Vec4d>>,
+    /// Number of vertices transformed so far.
+    misses: usize,
+}
+
+impl VertexCache {
+    fn new(size: usize) -> Self {
+        Self {
+            clip: vec![None; size],
+            misses: 0,
+        }
+    }
+
+    /// Forget every transformed vertex, for the next instance.
+    fn invalidate(&mut self) {
+        self.clip.fill(None);
+    }
+
+    fn get(&mut self, index: usize, vertex: &Vertex, transform: &Matrix4d) -> Vec4d {
+        *self.clip[index].get_or_insert_with(|| {
+            self.misses += 1;
+            mul_vector(transform, &[vertex.x, vertex.y, vertex.z, 1.])
+        })
+    }
+}
+
+impl DrawCall<'_> {
+    /// Rasterise every triangle of every instance into `image`, instance by instance, calling
+    /// `fragment` with the triangle and its perspective-correct barycentric weights. Each vertex
+    /// is transformed once per instance, however many triangles share it. If picking is enabled,
+    /// hits record the mesh of the image's current primitive and the index of the triangle in
+    /// the index buffer, and the current primitive is restored afterwards. Returns the number of
+    /// vertex transforms done.
+    pub fn draw<F>(&self, image: &mut Image, mut fragment: F) -> usize
+    where
+        F: FnMut(&Primitive, Vec3d) -> Pixel,
+    {
+        let mut cache = VertexCache::new(self.vertices.len());
+        let previous: Option<PrimitiveId> = image.primitive();
+
+        for (instance, transform) in self.instances.iter().enumerate() {
+            cache.invalidate();
+            for (triangle, face) in self.indices.iter().enumerate() {
+                let vertices: [usize; 3] = [face.0, face.1, face.2];
+                let clip: [Vec4d; 3] = vertices.map(|v| cache.get(v, &self.vertices[v], transform));
+
+                let primitive = Primitive {
+                    instance,
+                    triangle,
+                    vertices,
+                };
+                image.set_primitive(previous.map(|id| PrimitiveId { triangle, ..id }));
+                image.perspective_triangle(&clip, |weights| fragment(&primitive, weights));
+            }
+        }
+
+        image.set_primitive(previous);
+        cache.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Position;
+    use crate::image::depth::Compare;
+    use crate::image::pixel::WHITE;
+
+    /// Square of `n` by `n` cells spanning [-1, 1], two triangles per cell sharing vertices.
+    fn grid(n: usize) -> (Vec<Vertex>, Vec<Triangle>) {
+        let vertices: Vec<Vertex> = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| [x, y]))
+            .map(|p| p.map(|c| c as f64 * 2. / n as f64 - 1.))
+            .map(|[x, y]| Vertex::from([x, y, 0.]))
+            .collect();
+
+        let indices: Vec<Triangle> = (0..n)
+            .flat_map(|y| (0..n).map(move |x| x + (n + 1) * y))
+            .flat_map(|v| {
+                [
+                    Triangle(v, v + 1, v + n + 2),
+                    Triangle(v, v + n + 2, v + n + 1),
+                ]
+            })
+            .collect();
+
+        (vertices, indices)
+    }
+
+    /// Scale by a half and move by `x` and `y`.
+    fn placed(x: f64, y: f64) -> Matrix4d {
+        [
+            [0.5, 0., 0., x],
+            [0., 0.5, 0., y],
+            [0., 0., 0.5, 0.],
+            [0., 0., 0., 1.],
+        ]
+    }
+
+    #[test]
+    fn shared_vertices_are_transformed_once_per_instance() {
+        let (vertices, indices) = grid(4);
+        let instances: [Matrix4d; 3] = [placed(-0.5, -0.5), placed(0.5, -0.5), placed(0., 0.5)];
+        let call = DrawCall {
+            vertices: &vertices,
+            indices: &indices,
+            instances: &instances,
+        };
+
+        let mut image = Image::blank(32, 32);
+        let transformed: usize = call.draw(&mut image, |_, _| WHITE);
+        assert_eq!(transformed, 25 * 3);
+
+        // The same triangles drawn one at a time
+        let mut expected = Image::blank(32, 32);
+        for transform in &instances {
+            for face in &indices {
+                let clip: [Vec4d; 3] = [face.0, face.1, face.2].map(|v| {
+                    let Vertex { x, y, z } = vertices[v];
+                    mul_vector(transform, &[x, y, z, 1.])
+                });
+                expected.perspective_triangle(&clip, |_| WHITE);
+            }
+        }
+        assert!(image.ppm() == expected.ppm());
+    }
+
+    #[test]
+    fn fragments_know_their_primitive() {
+        let (vertices, indices) = grid(2);
+        let instances: [Matrix4d; 2] = [placed(-0.5, 0.), placed(0.5, 0.)];
+        let call = DrawCall {
+            vertices: &vertices,
+            indices: &indices,
+            instances: &instances,
+        };
+
+        let mut primitives: Vec<Primitive> = Vec::new();
+        let mut image = Image::blank(16, 16);
+        call.draw(&mut image, |primitive, _| {
+            primitives.push(*primitive);
+            Pixel {
+                red: 100 * primitive.instance as u8,
+                green: primitive.triangle as u8,
+                blue: 0,
+                alpha: 255,
+            }
+        });
+
+        assert!(primitives.iter().all(|p| {
+            let face = &indices[p.triangle];
+            p.vertices == [face.0, face.1, face.2]
+        }));
+        // Upper left triangles of the bottom left cells of the left and right copies
+        let at = |x: usize, y: usize| image.get(&Position { x, y }).unwrap();
+        assert_eq!([at(1, 6).red, at(1, 6).green], [0, 1]);
+        assert_eq!([at(9, 6).red, at(9, 6).green], [100, 1]);
+    }
+
+    #[test]
+    fn triangles_are_picked_with_the_current_mesh() {
+        let (vertices, indices) = grid(2);
+        let instances: [Matrix4d; 2] = [placed(-0.5, 0.), placed(0.5, 0.)];
+        let call = DrawCall {
+            vertices: &vertices,
+            indices: &indices,
+            instances: &instances,
+        };
+        let mesh = |mesh: usize| PrimitiveId { mesh, triangle: 0 };
+
+        let mut image = Image::blank(16, 16);
+        image.enable_picking();
+        image.set_primitive(Some(mesh(7)));
+        call.draw(&mut image, |_, _| WHITE);
+        assert_eq!(image.primitive(), Some(mesh(7)));
+
+        // Another mesh drawn over the right copy keeps its own id
+        let right: [Matrix4d; 1] = [placed(0.5, 0.)];
+        image.set_primitive(Some(mesh(8)));
+        image.depth_buffer_mut().set_compare(Compare::LessEqual);
+        DrawCall {
+            instances: &right,
+            ..call
+        }
+        .draw(&mut image, |_, _| WHITE);
+
+        let id = |x: usize, y: usize| image.pick(x, y).map(|hit| hit.id);
+        assert_eq!(
+            id(1, 6),
+            Some(PrimitiveId {
+                mesh: 7,
+                triangle: 1,
+            })
+        );
+        assert_eq!(
+            id(9, 6),
+            Some(PrimitiveId {
+                mesh: 8,
+                triangle: 1,
+            })
+        );
+        assert_eq!(id(8, 1), None);
+    }
+}
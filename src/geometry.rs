@@ -1,3 +1,5 @@
+use crate::math::vector::{Vec2d, Vec3d};
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vertex {
     pub x: f64,
@@ -5,57 +7,119 @@ pub struct Vertex {
     pub z: f64,
 }
 
+impl From<Vec3d> for Vertex {
+    fn from([x, y, z]: Vec3d) -> Self {
+        Vertex { x, y, z }
+    }
+}
+
+impl From<Vertex> for Vec3d {
+    fn from(vertex: Vertex) -> Self {
+        [vertex.x, vertex.y, vertex.z]
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Triangle(pub usize, pub usize, pub usize);
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct Geometry {
     pub vertices: Vec<Vertex>,
     pub faces: Vec<Triangle>,
+    pub uvs: Vec<Vec2d>,
+    /// Indices into `uvs` for each face, empty unless every face references texture coordinates.
+    pub uv_faces: Vec<Triangle>,
+    pub normals: Vec<Vec3d>,
+    /// Indices into `normals` for each face, empty unless every face references normals.
+    pub normal_faces: Vec<Triangle>,
 }
 
 impl Geometry {
     #[allow(clippy::just_underscores_and_digits)]
     pub fn decode_obj(input: &str) -> Self {
-        let parse_vertex_line = |line: &str| -> Vertex {
-            let [x, y, z]: [f64; 3] = line
-                .split_whitespace()
-                .skip(1) // Skip vertex marker 'f'
+        let parse_float_line = |line: &str| -> Vec<f64> {
+            line.split_whitespace()
+                .skip(1) // Skip marker 'v', 'vt' or 'vn'
                 .map(|token| token.parse::<f64>().expect("Cannot convert into `f64`"))
-                .collect::<Vec<f64>>()
+                .collect()
+        };
+
+        let parse_vertex_line = |line: &str| -> Vertex {
+            let [x, y, z]: [f64; 3] = parse_float_line(line)
                 .try_into()
                 .expect("Expected only three tokens");
 
             Vertex { x, y, z }
         };
 
-        let parse_face_line = |line: &str| -> Triangle {
+        // Texture coordinates may carry an optional third component, which is ignored
+        let parse_uv_line = |line: &str| -> Vec2d {
+            let tokens: Vec<f64> = parse_float_line(line);
+            [tokens[0], tokens[1]]
+        };
+
+        let parse_normal_line = |line: &str| -> Vec3d {
+            parse_float_line(line)
+                .try_into()
+                .expect("Expected only three tokens")
+        };
+
+        // Read the `slot`-th index of each 'v/vt/vn' element, if all three elements have one
+        let parse_face_line = |line: &str, slot: usize| -> Option<Triangle> {
             let [_0, _1, _2]: [usize; 3] = line
                 .split_whitespace()
                 .skip(1) // Skip face marker 'f'
-                .map(|elem| elem.split("/").next().unwrap())
+                .map(|elem| elem.split("/").nth(slot).filter(|token| !token.is_empty()))
                 // Subtract converted token, since OBJ faces index the vertices starting at 1
-                .map(|token| token.parse::<usize>().expect("Cannot convert into `usize`") - 1)
-                .collect::<Vec<usize>>()
+                .map(|token| {
+                    token.map(|t| t.parse::<usize>().expect("Cannot convert into `usize`") - 1)
+                })
+                .collect::<Option<Vec<usize>>>()?
                 .try_into()
                 .expect("Expected only three tokens");
 
-            Triangle(_0, _1, _2)
+            Some(Triangle(_0, _1, _2))
         };
 
-        let vertices: Vec<Vertex> = input
-            .lines()
-            .filter(|line| line.starts_with("v "))
-            .map(parse_vertex_line)
-            .collect();
+        let lines_with =
+            |marker: &'static str| input.lines().filter(move |line| line.starts_with(marker));
+
+        let vertices: Vec<Vertex> = lines_with("v ").map(parse_vertex_line).collect();
+        let uvs: Vec<Vec2d> = lines_with("vt ").map(parse_uv_line).collect();
+        let normals: Vec<Vec3d> = lines_with("vn ").map(parse_normal_line).collect();
 
-        let faces: Vec<Triangle> = input
-            .lines()
-            .filter(|line| line.starts_with("f "))
-            .map(parse_face_line)
+        let faces: Vec<Triangle> = lines_with("f ")
+            .map(|line| parse_face_line(line, 0).expect("Expected vertex indices"))
             .collect();
+        let uv_faces: Vec<Triangle> = lines_with("f ")
+            .map(|line| parse_face_line(line, 1))
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+        let normal_faces: Vec<Triangle> = lines_with("f ")
+            .map(|line| parse_face_line(line, 2))
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+
+        Geometry {
+            vertices,
+            faces,
+            uvs,
+            uv_faces,
+            normals,
+            normal_faces,
+        }
+    }
+
+    /// Indices of the faces ordered from the furthest from the viewer to the nearest, by the depth
+    /// of their centroids, so that transparent faces blend correctly over those behind them.
+    pub fn back_to_front(&self) -> Vec<usize> {
+        let depth = |face: &Triangle| {
+            self.vertices[face.0].z + self.vertices[face.1].z + self.vertices[face.2].z
+        };
 
-        Geometry { vertices, faces }
+        let mut order: Vec<usize> = (0..self.faces.len()).collect();
+        order.sort_by(|&a, &b| depth(&self.faces[a]).total_cmp(&depth(&self.faces[b])));
+        order
     }
 }
 
@@ -91,6 +155,10 @@ mod tests {
                 },
             ],
             faces: vec![Triangle(2, 1, 0), Triangle(3, 2, 0)],
+            uvs: vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]],
+            uv_faces: vec![Triangle(2, 1, 0), Triangle(3, 2, 0)],
+            normals: vec![[0., 1., 0.]],
+            normal_faces: vec![Triangle(0, 0, 0), Triangle(0, 0, 0)],
         };
 
         assert_eq!(
@@ -99,4 +167,22 @@ mod tests {
             "Cannot decode '.obj' file"
         );
     }
+
+    #[test]
+    fn decode_obj_file_without_uvs() {
+        let input: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1";
+        let geometry = Geometry::decode_obj(input);
+
+        assert_eq!(geometry.faces, vec![Triangle(0, 1, 2)]);
+        assert!(geometry.uvs.is_empty() && geometry.uv_faces.is_empty());
+        assert_eq!(geometry.normal_faces, vec![Triangle(0, 0, 0)]);
+    }
+
+    #[test]
+    fn faces_sorted_back_to_front() {
+        let input: &str = "v 0 0 0.5\nv 1 0 0.5\nv 0 1 0.5\nv 0 0 -0.5\nv 1 0 -0.5\nv 0 1 -0.5\nv 0 0 0\nf 1 2 3\nf 4 5 6\nf 7 2 3";
+        let geometry = Geometry::decode_obj(input);
+
+        assert_eq!(geometry.back_to_front(), vec![1, 2, 0]);
+    }
 }
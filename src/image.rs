@@ -1,108 +1,354 @@
+pub mod antialias;
+pub mod blend;
+pub mod depth;
+pub mod hdr;
+pub mod netpbm;
+pub mod occlusion;
+pub mod picking;
 pub mod pixel;
+pub mod png;
+pub mod raster;
+pub mod stroke;
+pub mod target;
+pub mod texture;
+pub mod tga;
+pub mod tiled;
 
+use std::convert::identity;
+
+use antialias::Multisample;
+use blend::Blend;
+use depth::{DepthBuffer, HierarchicalZ};
+use hdr::{HdrBuffer, ToneMap, Transfer};
+use netpbm::{Encoding, Format, Netpbm};
+use picking::{Hit, PrimitiveId};
 use pixel::Pixel;
+use raster::{Cull, Setup, Winding, clip_near, perspective_weights};
+use target::Attachment;
 
 use crate::geometry::Vertex;
+use crate::math::vector::{Vec2d, Vec3d, Vec4d, div, dot_product, weighted_sum};
 
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
-fn triangle_area(a: &Position, b: &Position, c: &Position) -> f64 {
-    let (ax, ay) = (a.x as f64, a.y as f64);
-    let (bx, by) = (b.x as f64, b.y as f64);
-    let (cx, cy) = (c.x as f64, c.y as f64);
-
-    (ax * (by - cy) + bx * (cy - ay) + cx * (ay - by)) / 2.0
+/// Corner of the image that `Position { x: 0, y: 0 }` refers to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Origin {
+    TopLeft,
+    BottomLeft,
 }
 
-fn bounding_box(positions: Vec<&Position>) -> (Position, Position) {
-    let min = Position {
-        x: positions.iter().map(|p| p.x).min().unwrap(),
-        y: positions.iter().map(|p| p.y).min().unwrap(),
-    };
-
-    let max = Position {
-        x: positions.iter().map(|p| p.x).max().unwrap(),
-        y: positions.iter().map(|p| p.y).max().unwrap(),
-    };
-
-    (min, max)
+/// Channels kept when the image is encoded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channels {
+    Rgb,
+    Rgba,
 }
 
 pub struct Image {
     width: usize,
     height: usize,
+    origin: Origin,
+    channels: Channels,
+    blend: Blend,
+    cull: Cull,
+    /// Winding on screen of the triangles that face the viewer.
+    front_face: Winding,
     data: Vec<Pixel>,
-    zbuffer: Vec<u8>,
+    zbuffer: DepthBuffer,
+    /// Coarse depth ranges of `zbuffer`, kept up to date as triangles are rasterised.
+    hiz: HierarchicalZ,
+    /// Linear render target, created when a triangle is first rendered into it.
+    hdr: Option<HdrBuffer>,
+    /// Per-sample colour and depth, when rendering with more than one sample per pixel.
+    multisample: Option<Multisample>,
+    /// Per-pixel outputs besides the colour, by name.
+    attachments: Vec<(String, Attachment)>,
+    /// Nearest triangle at each pixel, when picking is enabled.
+    hits: Option<Vec<Option<Hit>>>,
+    /// Id recorded for the triangles being rasterised.
+    primitive: Option<PrimitiveId>,
 }
 
 impl Image {
-    fn project_vertex(&self, v: &Vertex) -> (Position, u8) {
+    /// Continuous image coordinates of a vertex. Rasterisation always happens with `y` pointing
+    /// up, so that rendering does not depend on the image's origin.
+    fn screen(&self, v: &Vertex) -> Vec2d {
+        [
+            (v.x + 1.) * self.width as f64 / 2.,
+            (v.y + 1.) * self.height as f64 / 2.,
+        ]
+    }
+
+    pub(crate) fn project_vertex(&self, v: &Vertex) -> (Position, f64) {
+        let [x, y] = self.screen(v);
         (
             Position {
-                x: ((v.x + 1.0) * self.width as f64) as usize / 2,
-                y: ((v.y + 1.0) * self.height as f64) as usize / 2,
+                x: x as usize,
+                y: y as usize,
             },
-            ((v.z + 1.) * 255. / 2.) as u8,
+            self.zbuffer.depth_of(v.z),
         )
     }
 
+    /// Index into `data` and the depth buffer, whose rows are stored from the top down.
+    fn index(&self, position: &Position) -> Option<usize> {
+        if position.x >= self.width || position.y >= self.height {
+            return None;
+        }
+
+        let row: usize = match self.origin {
+            Origin::BottomLeft => self.height - position.y - 1,
+            Origin::TopLeft => position.y,
+        };
+        Some(position.x + self.width * row)
+    }
+
+    /// Index of a position produced by `project_vertex`, which is measured from the bottom left.
+    fn raster_index(&self, position: &Position) -> Option<usize> {
+        if position.x >= self.width || position.y >= self.height {
+            return None;
+        }
+
+        Some(position.x + self.width * (self.height - position.y - 1))
+    }
+
+    /// Rate of change of the barycentric weights of `i`, `j` and `k` per pixel step right and up
+    /// the image, used to derive screen-space derivatives of interpolated attributes.
+    pub fn barycentric_derivatives(&self, i: &Vertex, j: &Vertex, k: &Vertex) -> [Vec3d; 2] {
+        let (a, b, c) = (self.screen(i), self.screen(j), self.screen(k));
+        let doubled_area: f64 = a[0] * (b[1] - c[1]) + b[0] * (c[1] - a[1]) + c[0] * (a[1] - b[1]);
+
+        [
+            div(&[b[1] - c[1], c[1] - a[1], a[1] - b[1]], doubled_area),
+            div(&[c[0] - b[0], a[0] - c[0], b[0] - a[0]], doubled_area),
+        ]
+    }
+
     pub fn blank(width: usize, height: usize) -> Self {
         Self {
             width,
             height,
+            origin: Origin::BottomLeft,
+            channels: Channels::Rgb,
+            blend: Blend::Replace,
+            cull: Cull::Back,
+            front_face: Winding::Anticlockwise,
             data: vec![
                 Pixel {
                     red: 0,
                     green: 0,
-                    blue: 0
+                    blue: 0,
+                    alpha: 255,
                 };
                 width * height
             ],
-            zbuffer: vec![0; width * height],
+            zbuffer: DepthBuffer::new(width, height),
+            hiz: HierarchicalZ::new(width, height),
+            hdr: None,
+            multisample: None,
+            attachments: Vec::new(),
+            hits: None,
+            primitive: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Change which corner positions are measured from, without moving any pixels.
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = origin;
+    }
+
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    /// Choose whether alpha is kept when encoding, for formats that can store it.
+    pub fn set_channels(&mut self, channels: Channels) {
+        self.channels = channels;
+    }
+
+    pub fn blend(&self) -> Blend {
+        self.blend
+    }
+
+    /// Set how rasterised fragments combine with the pixels beneath them.
+    pub fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+
+    pub fn cull(&self) -> Cull {
+        self.cull
+    }
+
+    /// Choose which faces of triangles are discarded before rasterisation.
+    pub fn set_cull(&mut self, cull: Cull) {
+        self.cull = cull;
+    }
+
+    pub fn front_face(&self) -> Winding {
+        self.front_face
+    }
+
+    /// Choose the winding on screen of triangles that face the viewer.
+    pub fn set_front_face(&mut self, front_face: Winding) {
+        self.front_face = front_face;
+    }
+
+    /// Whether a triangle faces the viewer, by its winding on screen. Triangles with no area are
+    /// treated as facing away.
+    pub fn front_facing(&self, i: &Vertex, j: &Vertex, k: &Vertex) -> bool {
+        Setup::new([i, j, k].map(|v| self.screen(v)), [0.; 3])
+            .is_some_and(|setup| setup.winding() == self.front_face)
+    }
+
+    /// Fill every pixel with `pixel`, and clear the depth buffer, any HDR target, any samples, any
+    /// attachments and any picking records.
+    pub fn clear(&mut self, pixel: Pixel) {
+        self.data.fill(pixel);
+        self.zbuffer.clear();
+        self.hiz.invalidate();
+        if let Some(hdr) = &mut self.hdr {
+            hdr.clear();
+        }
+        if let Some(multisample) = &mut self.multisample {
+            multisample.clear(pixel);
+        }
+        for (_, attachment) in &mut self.attachments {
+            attachment.clear();
+        }
+        if let Some(hits) = &mut self.hits {
+            hits.fill(None);
         }
     }
 
+    pub fn get(&self, position: &Position) -> Option<Pixel> {
+        self.index(position).map(|index| self.data[index])
+    }
+
     pub fn set(&mut self, pixel: Pixel, position: &Position) {
-        if position.x < self.width && position.y < self.height {
-            self.data[position.x + self.width * (self.width - position.y - 1)] = pixel;
+        if let Some(index) = self.index(position) {
+            self.data[index] = pixel;
+            if let Some(multisample) = &mut self.multisample {
+                multisample.fill(index, pixel);
+            }
         }
     }
 
-    fn get_zbuffer(&mut self, position: &Position) -> u8 {
-        if position.x < self.width && position.y < self.height {
-            self.zbuffer[position.x + self.width * (self.width - position.y - 1)]
-        } else {
-            0
+    /// Depth stored at `position`, or the clear value outside the image.
+    pub fn depth(&self, position: &Position) -> f64 {
+        match self.index(position) {
+            Some(index) => self.zbuffer.get(index),
+            None => self.zbuffer.clear_value(),
         }
     }
 
-    fn set_zbuffer(&mut self, z: u8, position: &Position) {
-        if position.x < self.width && position.y < self.height {
-            self.zbuffer[position.x + self.width * (self.width - position.y - 1)] = z;
+    pub fn hdr_buffer(&self) -> Option<&HdrBuffer> {
+        self.hdr.as_ref()
+    }
+
+    /// Overwrite the pixels with the tone mapped contents of the HDR target, if there is one.
+    pub fn tone_map(&mut self, tone_map: ToneMap, exposure: f64, transfer: Transfer) {
+        if let Some(hdr) = &self.hdr {
+            self.data = hdr.resolve(tone_map, exposure, transfer);
         }
     }
 
+    pub fn depth_buffer(&self) -> &DepthBuffer {
+        &self.zbuffer
+    }
+
+    pub fn depth_buffer_mut(&mut self) -> &mut DepthBuffer {
+        self.hiz.invalidate();
+        &mut self.zbuffer
+    }
+
+    /// Store `depth` at `index` if it passes the depth test, returning whether it did.
+    fn depth_test(&mut self, depth: f64, index: usize) -> bool {
+        self.zbuffer.test_and_set(index, depth)
+    }
+
     pub fn line(&mut self, colour: Pixel, start: &Position, end: &Position) {
+        self.bresenham(start, end, |image, position| image.set(colour, &position));
+    }
+
+    /// Draw the edge between two vertices, skipping pixels hidden behind the depth buffer when a
+    /// `bias` is given. The bias moves the edge towards the viewer in projected `z`, so that edges
+    /// of the faces already in the depth buffer are not hidden by those faces themselves.
+    pub fn edge(&mut self, colour: Pixel, i: &Vertex, j: &Vertex, bias: Option<f64>) {
+        let (start, _) = self.project_vertex(i);
+        let (end, _) = self.project_vertex(j);
+        let steps: f64 = (start.x.abs_diff(end.x))
+            .max(start.y.abs_diff(end.y))
+            .max(1) as f64;
+
+        self.bresenham(&start, &end, |image, position| {
+            let Some(index) = image.raster_index(&position) else {
+                return;
+            };
+
+            if let Some(bias) = bias {
+                let step: usize = position
+                    .x
+                    .abs_diff(start.x)
+                    .max(position.y.abs_diff(start.y));
+                let t: f64 = step as f64 / steps;
+                let depth: f64 = image.zbuffer.depth_of(i.z + t * (j.z - i.z) + bias);
+                if !image
+                    .zbuffer
+                    .compare()
+                    .test(depth, image.zbuffer.get(index))
+                {
+                    return;
+                }
+            }
+
+            image.data[index] = colour;
+            if let Some(multisample) = &mut image.multisample {
+                multisample.fill(index, colour);
+            }
+        });
+    }
+
+    /// Call `plot` with each position on the line from `start` to `end`.
+    fn bresenham<P>(&mut self, start: &Position, end: &Position, mut plot: P)
+    where
+        P: FnMut(&mut Self, Position),
+    {
         let diff_x: i64 = end.x as i64 - start.x as i64;
         let diff_y: i64 = end.y as i64 - start.y as i64;
 
         if diff_y.abs() < diff_x.abs() {
             if start.x > end.x {
-                self.line_low(colour, end, start);
+                self.line_low(end, start, &mut plot);
             } else {
-                self.line_low(colour, start, end);
+                self.line_low(start, end, &mut plot);
             }
         } else if start.y > end.y {
-            self.line_high(colour, end, start);
+            self.line_high(end, start, &mut plot);
         } else {
-            self.line_high(colour, start, end);
+            self.line_high(start, end, &mut plot);
         }
     }
 
-    fn line_low(&mut self, colour: Pixel, start: &Position, end: &Position) {
+    fn line_low<P>(&mut self, start: &Position, end: &Position, plot: &mut P)
+    where
+        P: FnMut(&mut Self, Position),
+    {
         let dx: i64 = end.x as i64 - start.x as i64;
 
         let mut dy: i64 = end.y as i64 - start.y as i64;
@@ -117,7 +363,7 @@ impl Image {
         let mut dd: i64 = (2 * dy) - dx;
 
         for x in start.x..=end.x {
-            self.set(colour, &Position { x, y: y as usize });
+            plot(self, Position { x, y: y as usize });
             if dd > 0 {
                 y += yi;
                 dd += 2 * (dy - dx);
@@ -127,7 +373,10 @@ impl Image {
         }
     }
 
-    fn line_high(&mut self, colour: Pixel, start: &Position, end: &Position) {
+    fn line_high<P>(&mut self, start: &Position, end: &Position, plot: &mut P)
+    where
+        P: FnMut(&mut Self, Position),
+    {
         let mut dx: i64 = end.x as i64 - start.x as i64;
         let mut xi: i64 = 1;
 
@@ -142,7 +391,7 @@ impl Image {
         let mut x: i64 = start.x as i64;
 
         for y in start.y..=end.y {
-            self.set(colour, &Position { x: x as usize, y });
+            plot(self, Position { x: x as usize, y });
             if dd > 0 {
                 x += xi;
                 dd += 2 * (dx - dy);
@@ -153,56 +402,629 @@ impl Image {
     }
 
     pub fn triangle(&mut self, _colour: Pixel, i: &Vertex, j: &Vertex, k: &Vertex) {
-        let (a, az): (Position, u8) = self.project_vertex(i);
-        let (b, bz): (Position, u8) = self.project_vertex(j);
-        let (c, cz): (Position, u8) = self.project_vertex(k);
+        let depths: Vec3d = [i.z, j.z, k.z];
+
+        self.shaded_triangle(i, j, k, |barycentric| {
+            let z = ((dot_product(&barycentric, &depths) + 1.) * 255. / 2.) as u8;
+            Pixel {
+                red: z,
+                green: z,
+                blue: z,
+                alpha: 255,
+            }
+        });
+    }
+
+    /// Rasterise a triangle, calling `fragment` with the barycentric weights of `i`, `j` and `k`
+    /// for every pixel that passes the depth test, and blending its result into the image. When
+    /// multisampling, the result is blended into the covered samples instead, until `resolve`.
+    pub fn shaded_triangle<F>(&mut self, i: &Vertex, j: &Vertex, k: &Vertex, mut fragment: F)
+    where
+        F: FnMut(Vec3d) -> Pixel,
+    {
+        if let Some(setup) = self.setup(i, j, k) {
+            self.shade(&setup, identity, |_, _, barycentric| fragment(barycentric));
+        }
+    }
 
-        if triangle_area(&a, &b, &c) < 0.0 {
+    /// Rasterise a triangle given by its vertices in clip space, as `shaded_triangle` does, but
+    /// with `fragment` called with perspective-correct barycentric weights, so that attributes
+    /// interpolated with them do not warp. Depth stays linear in screen space, being divided by
+    /// `w` already. Triangles are clipped to the near plane, where `z = w`, before the divide,
+    /// and whatever is left is drawn as a fan with weights still over the vertices of `clip`,
+    /// which are also the weights recorded for picking.
+    pub fn perspective_triangle<F>(&mut self, clip: &[Vec4d; 3], mut fragment: F)
+    where
+        F: FnMut(Vec3d) -> Pixel,
+    {
+        let polygon: Vec<(Vec4d, Vec3d)> = clip_near(clip);
+        if polygon.iter().any(|&([_, _, _, w], _)| w <= 0.) {
             return;
         }
 
-        let (bottom_left, top_right): (Position, Position) = bounding_box(vec![&a, &b, &c]);
-
-        for x in bottom_left.x..=top_right.x {
-            for y in bottom_left.y..=top_right.y {
-                let px = &Position { x, y };
-                let total_area: f64 = triangle_area(&a, &b, &c);
-
-                let alpha: f64 = triangle_area(px, &a, &b) / total_area;
-                let beta: f64 = triangle_area(px, &b, &c) / total_area;
-                let gamma: f64 = triangle_area(px, &c, &a) / total_area;
-
-                if alpha.is_sign_positive() && beta.is_sign_positive() && gamma.is_sign_positive() {
-                    let z = (alpha * az as f64 + beta * bz as f64 + gamma * cz as f64) as u8;
-                    if z > self.get_zbuffer(px) {
-                        self.set_zbuffer(z, px);
-                        self.set(
-                            Pixel {
-                                red: z,
-                                green: z,
-                                blue: z,
-                            },
-                            px,
-                        );
-                    }
+        for n in 1..polygon.len().saturating_sub(1) {
+            let [(a, p), (b, q), (c, r)] = [polygon[0], polygon[n], polygon[n + 1]];
+            let [i, j, k] = [a, b, c].map(|[x, y, z, w]| Vertex::from([x / w, y / w, z / w]));
+            let w: Vec3d = [a[3], b[3], c[3]];
+            if let Some(setup) = self.setup(&i, &j, &k) {
+                let original =
+                    |weights: Vec3d| weighted_sum(&[p, q, r], &perspective_weights(&weights, &w));
+                self.shade(&setup, original, |_, _, weights| fragment(weights));
+            }
+        }
+    }
+
+    /// Rasterise a triangle that has been set up, as `shaded_triangle` does, with `fragment` also
+    /// given the attachments and the index of its pixel, and with the weights it and picking get
+    /// mapped from the screen-space ones by `map`.
+    fn shade<M, F>(&mut self, setup: &Setup, map: M, mut fragment: F)
+    where
+        M: Fn(Vec3d) -> Vec3d,
+        F: FnMut(&mut [(String, Attachment)], usize, Vec3d) -> Pixel,
+    {
+        if self.multisample.is_some() {
+            self.rasterise_multisample(setup, map, |image, index, barycentric, coverage| {
+                let source: Pixel = fragment(&mut image.attachments, index, barycentric);
+                let blend: Blend = image.blend;
+                if let Some(multisample) = &mut image.multisample {
+                    multisample.write(index, coverage, source, blend);
                 }
+            });
+            return;
+        }
+
+        self.rasterise(setup, map, |image, index, barycentric| {
+            let source: Pixel = fragment(&mut image.attachments, index, barycentric);
+            image.data[index] = image.blend.apply(source, image.data[index]);
+        });
+    }
+
+    /// Rasterise a triangle into the depth buffer only, leaving the pixels as they are.
+    pub fn depth_triangle(&mut self, i: &Vertex, j: &Vertex, k: &Vertex) {
+        if let Some(setup) = self.setup(i, j, k) {
+            self.rasterise(&setup, identity, |_, _, _| {});
+        }
+    }
+
+    /// Rasterise a triangle into the linear HDR target, storing the radiance `fragment` returns
+    /// for every pixel that passes the depth test. Blending and multisampling do not apply.
+    pub fn hdr_triangle<F>(&mut self, i: &Vertex, j: &Vertex, k: &Vertex, mut fragment: F)
+    where
+        F: FnMut(Vec3d) -> Vec3d,
+    {
+        let Some(setup) = self.setup(i, j, k) else {
+            return;
+        };
+        let (width, height) = (self.width, self.height);
+        self.hdr
+            .get_or_insert_with(|| HdrBuffer::new(width, height));
+
+        self.rasterise(&setup, identity, |image, index, barycentric| {
+            let radiance: Vec3d = fragment(barycentric);
+            if let Some(hdr) = &mut image.hdr {
+                hdr.set(index, &radiance);
             }
+        });
+    }
+
+    /// Snap a triangle to the sub-pixel grid, with its depths, or `None` if it is culled.
+    fn setup(&self, i: &Vertex, j: &Vertex, k: &Vertex) -> Option<Setup> {
+        let setup = Setup::new(
+            [i, j, k].map(|v| self.screen(v)),
+            [i.z, j.z, k.z].map(|z| self.zbuffer.depth_of(z)),
+        )?;
+
+        let front: bool = setup.winding() == self.front_face;
+        match (self.cull, front) {
+            (Cull::Back, false) | (Cull::Front, true) => None,
+            _ => Some(setup),
         }
     }
 
+    /// Call `write` with the index and barycentric weights of every pixel of a triangle whose
+    /// centre the triangle covers and that passes the depth test. Pixel centres on an edge are
+    /// only covered by the triangle to the edge's right or below it, so triangles sharing an edge
+    /// draw each of its pixels once. Depth is tested before `write` is called, and blocks of
+    /// pixels where the triangle is behind everything already drawn are skipped whole. The
+    /// weights given to `write` and recorded for picking are mapped by `map` first.
+    fn rasterise<M, W>(&mut self, setup: &Setup, map: M, mut write: W)
+    where
+        M: Fn(Vec3d) -> Vec3d,
+        W: FnMut(&mut Self, usize, Vec3d),
+    {
+        let Some(bounds) = setup.bounds(self.width, self.height) else {
+            return;
+        };
+
+        setup.blocks(bounds, |block| {
+            if self.hiz.occludes(&self.zbuffer, block) {
+                return;
+            }
+
+            let mut written: bool = false;
+            setup.covered_pixels(block, |x, y, values| {
+                let weights: Vec3d = setup.weights(values);
+                let index: usize = x + self.width * (self.height - y - 1);
+                if self.depth_test(setup.depth(&weights), index) {
+                    written = true;
+                    let weights: Vec3d = map(weights);
+                    self.record_hit(index, weights);
+                    write(self, index, weights);
+                }
+            });
+            if written {
+                self.hiz.touch(block.columns[0], block.rows[0]);
+            }
+        });
+    }
+
     pub fn ppm(&self) -> Vec<u8> {
-        let mut ppm = Vec::new();
+        Netpbm::from(self).encode(Format::Ppm(Encoding::Binary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::depth::Compare;
+    use crate::image::texture::Texture;
+    use crate::math::matrix::{Matrix4d, mul_vector};
+    use crate::math::transform::perspective;
+    use crate::math::vector::{cross_product, scalar_mul, sub};
+
+    const SIZES: [(usize, usize); 5] = [(1, 1), (3, 800), (800, 3), (1920, 1080), (7, 5)];
+
+    fn colour(value: u8) -> Pixel {
+        Pixel {
+            red: value,
+            green: 255 - value,
+            blue: value / 2,
+            alpha: 255,
+        }
+    }
+
+    /// Pixel at `row` and `column` of a PPM, counting rows from the top.
+    fn ppm_pixel(ppm: &[u8], width: usize, height: usize, column: usize, row: usize) -> Pixel {
+        let header: usize = format!("P6\n{width} {height}\n255\n").len();
+        let offset: usize = header + 3 * (column + width * row);
+
+        Pixel {
+            red: ppm[offset],
+            green: ppm[offset + 1],
+            blue: ppm[offset + 2],
+            alpha: 255,
+        }
+    }
+
+    fn corners(width: usize, height: usize) -> [Position; 4] {
+        [
+            (0, 0),
+            (width - 1, 0),
+            (0, height - 1),
+            (width - 1, height - 1),
+        ]
+        .map(|(x, y)| Position { x, y })
+    }
+
+    #[test]
+    fn set_and_get_corners() {
+        for (width, height) in SIZES {
+            for origin in [Origin::BottomLeft, Origin::TopLeft] {
+                let mut img = Image::blank(width, height);
+                img.set_origin(origin);
+
+                for (n, corner) in corners(width, height).iter().enumerate() {
+                    img.set(colour(60 * n as u8 + 10), corner);
+                }
+
+                let ppm: Vec<u8> = img.ppm();
+                assert_eq!(
+                    ppm.len(),
+                    format!("P6\n{width} {height}\n255\n").len() + 3 * width * height
+                );
+
+                // Later writes win where corners coincide, as in 1-pixel wide images
+                for (n, corner) in corners(width, height).iter().enumerate() {
+                    let expected = colour(60 * n as u8 + 10);
+                    let later = corners(width, height)[n + 1..]
+                        .iter()
+                        .any(|other| other.x == corner.x && other.y == corner.y);
+                    if later {
+                        continue;
+                    }
+
+                    let row = match origin {
+                        Origin::BottomLeft => height - 1 - corner.y,
+                        Origin::TopLeft => corner.y,
+                    };
+                    assert_eq!(
+                        img.get(corner),
+                        Some(expected),
+                        "{width}x{height} {origin:?}"
+                    );
+                    assert_eq!(ppm_pixel(&ppm, width, height, corner.x, row), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_positions_are_ignored() {
+        for (width, height) in SIZES {
+            let mut img = Image::blank(width, height);
+            let outside = [
+                Position { x: width, y: 0 },
+                Position { x: 0, y: height },
+                Position {
+                    x: width,
+                    y: height,
+                },
+            ];
+
+            for position in &outside {
+                img.set(colour(200), position);
+                assert_eq!(img.get(position), None);
+                assert_eq!(img.depth(position), img.depth_buffer().clear_value());
+            }
+            assert!(
+                img.ppm()
+                    .iter()
+                    .skip(format!("P6\n{width} {height}\n255\n").len())
+                    .all(|&b| b == 0)
+            );
+        }
+    }
+
+    #[test]
+    fn full_screen_quad_covers_every_pixel() {
+        let corners: [Vertex; 4] =
+            [[-1., -1., 0.], [1., -1., 0.], [1., 1., 0.], [-1., 1., 0.]].map(Vertex::from);
+
+        for (width, height) in SIZES {
+            for origin in [Origin::BottomLeft, Origin::TopLeft] {
+                let mut img = Image::blank(width, height);
+                img.set_origin(origin);
+
+                img.shaded_triangle(&corners[0], &corners[1], &corners[2], |_| colour(100));
+                img.shaded_triangle(&corners[0], &corners[2], &corners[3], |_| colour(100));
+
+                assert!(
+                    img.depth_buffer()
+                        .values()
+                        .iter()
+                        .all(|&depth| (depth - 0.5).abs() < 1e-9),
+                    "{width}x{height} {origin:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cull_mode_and_front_face() {
+        use Winding::{Anticlockwise, Clockwise};
+        let [a, b, c] = [[-1., -1., 0.], [1., -1., 0.], [-1., 1., 0.]].map(Vertex::from);
+        let drawn = |cull: Cull, front_face: Winding| {
+            let mut img = Image::blank(8, 8);
+            img.set_cull(cull);
+            img.set_front_face(front_face);
+            img.depth_buffer_mut().set_compare(Compare::Always);
+            let mut drawn = [false; 2];
+            img.shaded_triangle(&a, &b, &c, |_| {
+                drawn[0] = true;
+                colour(100)
+            });
+            img.shaded_triangle(&a, &c, &b, |_| {
+                drawn[1] = true;
+                colour(100)
+            });
+            (drawn, img.front_facing(&a, &b, &c))
+        };
+
+        assert_eq!(drawn(Cull::Back, Anticlockwise), ([true, false], true));
+        assert_eq!(drawn(Cull::Front, Anticlockwise), ([false, true], true));
+        assert_eq!(drawn(Cull::Back, Clockwise), ([false, true], false));
+        assert_eq!(drawn(Cull::None, Clockwise), ([true, true], false));
+    }
+
+    #[test]
+    fn clockwise_triangles_are_interpolated_in_vertex_order() {
+        let [a, b, c] = [[-1., -1., 0.], [1., -1., 0.], [-1., 1., 0.]].map(Vertex::from);
+        let render = |i: &Vertex, j: &Vertex, k: &Vertex| {
+            let mut img = Image::blank(8, 8);
+            img.set_cull(Cull::None);
+            img.shaded_triangle(i, j, k, |[u, v, w]| Pixel {
+                red: (u * 255.) as u8,
+                green: (v * 255.) as u8,
+                blue: (w * 255.) as u8,
+                alpha: 255,
+            });
+            img.data
+        };
+
+        // Swapping the last two vertices swaps the weights given for them, and nothing else
+        let swapped: Vec<Pixel> = render(&a, &c, &b)
+            .into_iter()
+            .map(|p| Pixel {
+                green: p.blue,
+                blue: p.green,
+                ..p
+            })
+            .collect();
+        assert!(render(&a, &b, &c) == swapped);
+    }
 
-        ppm.extend_from_slice(b"P6\n");
-        ppm.extend_from_slice(format!("{} {}\n", self.width, self.height).as_bytes());
-        ppm.extend_from_slice(b"255\n");
+    #[test]
+    fn adjacent_triangles_neither_overlap_nor_crack() {
+        // Fan around the centre of a pixel, out to points on the border at sub-pixel offsets, so
+        // that some edges run exactly through pixel centres
+        let centre = Vertex::from([-1. / 16., -1. / 16., 0.]);
+        let border: Vec<Vertex> = [
+            [-1., -1.],
+            [-0.3, -1.],
+            [1., -1.],
+            [1., 0.37],
+            [1., 1.],
+            [0.1, 1.],
+            [-1., 1.],
+            [-1., -0.51],
+        ]
+        .map(|[x, y]| Vertex::from([x, y, 0.]))
+        .to_vec();
 
-        for pixel in &self.data {
-            ppm.push(pixel.red);
-            ppm.push(pixel.green);
-            ppm.push(pixel.blue);
+        let mut img = Image::blank(16, 16);
+        img.set_blend(Blend::Additive);
+        img.depth_buffer_mut().set_compare(Compare::Always);
+        for n in 0..border.len() {
+            let next: &Vertex = &border[(n + 1) % border.len()];
+            img.shaded_triangle(&centre, &border[n], next, |_| colour(1));
         }
 
-        ppm
+        assert!(img.data.iter().all(|&p| p == colour(1)));
+    }
+
+    #[test]
+    fn hidden_fragments_are_not_shaded() {
+        let near: [Vertex; 3] = [[-1., -1., 0.5], [3., -1., 0.5], [-1., 3., 0.5]].map(Vertex::from);
+        let far: [Vertex; 3] = [[-1., -1., 0.], [3., -1., 0.], [-1., 3., 0.]].map(Vertex::from);
+
+        let mut img = Image::blank(40, 40);
+        img.shaded_triangle(&near[0], &near[1], &near[2], |_| colour(100));
+
+        let mut shaded: usize = 0;
+        img.shaded_triangle(&far[0], &far[1], &far[2], |_| {
+            shaded += 1;
+            colour(200)
+        });
+        assert_eq!(shaded, 0);
+
+        // Changing the depth test must not leave stale coarse depths behind
+        img.depth_buffer_mut().set_compare(Compare::Greater);
+        img.shaded_triangle(&far[0], &far[1], &far[2], |_| colour(200));
+        assert!(img.data.iter().all(|&p| p == colour(200)));
+
+        img.clear(colour(0));
+        img.depth_buffer_mut().set_compare(Compare::Less);
+        img.shaded_triangle(&far[0], &far[1], &far[2], |_| colour(200));
+        assert!(img.data.iter().all(|&p| p == colour(200)));
+    }
+
+    #[test]
+    fn textured_tilted_quad_is_perspective_correct() {
+        // Quad leaning away from the camera, with `y` rising as it recedes along `z = -3 - y`
+        let corners: [Vec3d; 4] = [
+            [-1., -1., -2.],
+            [1., -1., -2.],
+            [1., 1., -4.],
+            [-1., 1., -4.],
+        ];
+        let uvs: [Vec2d; 4] = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+        let projection: Matrix4d = perspective(std::f64::consts::FRAC_PI_2, 1., 1., 10.);
+        let clip: [Vec4d; 4] = corners.map(|[x, y, z]| mul_vector(&projection, &[x, y, z, 1.]));
+
+        // Ramps of `u` in red and `v` in green, bilinearly filtered
+        let texels: Vec<Pixel> = (0..256 * 256)
+            .map(|n| Pixel {
+                red: (n % 256) as u8,
+                green: 255 - (n / 256) as u8,
+                blue: 0,
+                alpha: 255,
+            })
+            .collect();
+        let texture = Texture::new(256, 256, texels);
+
+        let size: usize = 64;
+        let render = |correct: bool| {
+            let mut img = Image::blank(size, size);
+            for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+                let uvs: [Vec2d; 3] = [uvs[a], uvs[b], uvs[c]];
+                let shade = |weights: Vec3d| texture.sample(&weighted_sum(&uvs, &weights));
+                match correct {
+                    true => img.perspective_triangle(&[clip[a], clip[b], clip[c]], shade),
+                    false => {
+                        let [i, j, k] = [a, b, c].map(|n| {
+                            let [x, y, z, w] = clip[n];
+                            Vertex::from([x / w, y / w, z / w])
+                        });
+                        img.shaded_triangle(&i, &j, &k, shade);
+                    }
+                }
+            }
+            img
+        };
+        let (correct, affine) = (render(true), render(false));
+
+        // Intersect the ray through each pixel centre with the plane of the quad
+        let (mut checked, mut warped) = (0, 0);
+        for y in 0..size {
+            for x in 0..size {
+                let [sx, sy] = [x, y].map(|p| (p as f64 + 0.5) * 2. / size as f64 - 1.);
+                let t: f64 = 3. / (1. - sy);
+                let uv: Vec2d = [(t * sx + 1.) / 2., (t * sy + 1.) / 2.];
+                if uv.iter().any(|&c| !(0.02..=0.98).contains(&c)) {
+                    continue;
+                }
+
+                let expected: Pixel = texture.sample(&uv);
+                let position = Position { x, y };
+                let error = |img: &Image| {
+                    let pixel: Pixel = img.get(&position).unwrap();
+                    let red = (pixel.red as i32 - expected.red as i32).abs();
+                    red.max((pixel.green as i32 - expected.green as i32).abs())
+                };
+                assert!(error(&correct) <= 1, "{x}, {y}");
+                checked += 1;
+                warped += (error(&affine) > 8) as usize;
+            }
+        }
+
+        assert!(checked > 500);
+        assert!(
+            warped > 100,
+            "screen-space interpolation should warp the texture"
+        );
+    }
+
+    #[test]
+    fn triangles_crossing_the_near_plane_are_clipped() {
+        let projection: Matrix4d = perspective(std::f64::consts::FRAC_PI_2, 1., 1., 10.);
+
+        // Floors running from far in front up to just in front of the eye and past it
+        for near in [-1e-7, 0.5] {
+            let corners: [Vec3d; 3] = [[1., -1., -3.], [-1., -1., -3.], [0., -1., near]];
+            let clip: [Vec4d; 3] = corners.map(|[x, y, z]| mul_vector(&projection, &[x, y, z, 1.]));
+
+            let mut img = Image::blank(32, 32);
+            let mut drawn: usize = 0;
+            img.perspective_triangle(&clip, |weights| {
+                // The ray through the pixel meets the floor where `z = 1 / y` on the near plane
+                let [_, y, _, w] = weighted_sum(&clip, &weights);
+                let z: f64 = weighted_sum(&corners, &weights)[2];
+                assert!(weights.iter().all(|weight| (0. ..=1.).contains(weight)));
+                assert!(z <= -1. + 1e-9 && (z - w / y).abs() < 1e-9, "{near}");
+                drawn += 1;
+                pixel::WHITE
+            });
+
+            assert!(drawn > 32, "{near}");
+            assert_eq!(img.get(&Position { x: 16, y: 1 }), Some(pixel::WHITE));
+        }
+    }
+
+    #[test]
+    fn picked_weights_are_over_the_original_triangle() {
+        let projection: Matrix4d = perspective(std::f64::consts::FRAC_PI_2, 1., 1., 10.);
+        let size: usize = 32;
+
+        // Half of a quad leaning away from the camera, and a floor crossing the near plane
+        for corners in [
+            [[-1., -1., -2.], [1., -1., -2.], [1., 1., -4.]],
+            [[1., -1., -3.], [-1., -1., -3.], [0., -1., 0.5]],
+        ] {
+            let clip: [Vec4d; 3] = corners.map(|[x, y, z]| mul_vector(&projection, &[x, y, z, 1.]));
+            let mut img = Image::blank(size, size);
+            img.enable_picking();
+            img.set_primitive(Some(PrimitiveId {
+                mesh: 0,
+                triangle: 0,
+            }));
+            img.perspective_triangle(&clip, |_| pixel::WHITE);
+
+            // Where the ray through each pixel centre meets the plane of the triangle
+            let [a, b, c] = corners;
+            let normal: Vec3d = cross_product(&sub(&b, &a), &sub(&c, &a));
+            let mut picked: usize = 0;
+            for y in 0..size {
+                for x in 0..size {
+                    let Some(hit) = img.pick(x, y) else {
+                        continue;
+                    };
+                    let [sx, sy] = [x, y].map(|p| (p as f64 + 0.5) * 2. / size as f64 - 1.);
+                    let ray: Vec3d = [sx, sy, -1.];
+                    let point: Vec3d =
+                        scalar_mul(&ray, dot_product(&normal, &a) / dot_product(&normal, &ray));
+                    let area = |p: &Vec3d, q: &Vec3d| {
+                        dot_product(&cross_product(&sub(q, p), &sub(&point, p)), &normal)
+                    };
+                    let total: f64 = dot_product(&normal, &normal);
+                    let expected: Vec3d =
+                        [area(&b, &c), area(&c, &a), area(&a, &b)].map(|w| w / total);
+
+                    // Vertices are snapped to the sub-pixel grid, so the weights only come close
+                    assert!(
+                        (0..3).all(|n| (hit.barycentric[n] - expected[n]).abs() < 1e-3),
+                        "{x}, {y}: {:?} {expected:?}",
+                        hit.barycentric
+                    );
+                    picked += 1;
+                }
+            }
+            assert!(picked > 32);
+        }
+    }
+
+    #[test]
+    fn fragments_blend_into_the_image() {
+        let corners: [Vertex; 3] = [[-1., -1., 0.], [3., -1., 0.], [-1., 3., 0.]].map(Vertex::from);
+        let half_red = Pixel {
+            alpha: 128,
+            ..pixel::RED
+        };
+
+        let mut img = Image::blank(4, 4);
+        img.clear(pixel::BLUE);
+        img.set_blend(Blend::AlphaOver);
+        img.depth_buffer_mut().set_compare(depth::Compare::Always);
+
+        img.shaded_triangle(&corners[0], &corners[1], &corners[2], |_| half_red);
+        assert!(
+            img.data
+                .iter()
+                .all(|&p| p == Blend::AlphaOver.apply(half_red, pixel::BLUE))
+        );
+
+        img.set_blend(Blend::Replace);
+        img.shaded_triangle(&corners[0], &corners[1], &corners[2], |_| half_red);
+        assert!(img.data.iter().all(|&p| p == half_red));
+    }
+
+    #[test]
+    fn hdr_target_is_tone_mapped() {
+        let corners: [Vertex; 3] = [[-1., -1., 0.], [3., -1., 0.], [-1., 3., 0.]].map(Vertex::from);
+
+        let mut img = Image::blank(2, 2);
+        assert!(img.hdr_buffer().is_none());
+        img.hdr_triangle(&corners[0], &corners[1], &corners[2], |_| [4., 1., 0.]);
+        assert!(
+            img.hdr_buffer()
+                .unwrap()
+                .values()
+                .iter()
+                .all(|&v| v == [4., 1., 0.])
+        );
+
+        img.tone_map(ToneMap::Reinhard, 0., Transfer::Gamma(1.));
+        assert!(img.data.iter().all(|&p| p == [204., 127.5, 0.].into()));
+    }
+
+    #[test]
+    fn origin_does_not_change_rendering() {
+        let (i, j, k): (Vertex, Vertex, Vertex) = (
+            [-0.9, -0.8, 0.].into(),
+            [0.7, -0.2, 0.].into(),
+            [-0.1, 0.9, 0.].into(),
+        );
+
+        for (width, height) in SIZES {
+            let render = |origin: Origin| {
+                let mut img = Image::blank(width, height);
+                img.set_origin(origin);
+                img.shaded_triangle(&i, &j, &k, |_| colour(150));
+                img.ppm()
+            };
+
+            let bottom_left: Vec<u8> = render(Origin::BottomLeft);
+            assert!(width * height == 1 || bottom_left.contains(&150));
+            assert_eq!(bottom_left, render(Origin::TopLeft), "{width}x{height}");
+        }
     }
 }
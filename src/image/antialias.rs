@@ -0,0 +1,440 @@
+use std::f64::consts::PI;
+
+use crate::image::Image;
+use crate::image::blend::Blend;
+use crate::image::depth::DepthBuffer;
+use crate::image::pixel::Pixel;
+use crate::image::raster::{self, Fixed, Setup};
+use crate::math::vector::{Vec2d, Vec3d, Vec4d, add, scalar_mul};
+
+/// Sample positions within a pixel, relative to its centre in sixteenths of a pixel, following
+/// the standard Direct3D patterns.
+const PATTERN_2: [(i8, i8); 2] = [(4, 4), (-4, -4)];
+const PATTERN_4: [(i8, i8); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+const PATTERN_8: [(i8, i8); 8] = [
+    (1, -3),
+    (-1, 3),
+    (5, 1),
+    (-3, -5),
+    (-5, 5),
+    (-7, -1),
+    (3, 7),
+    (7, -7),
+];
+const PATTERN_16: [(i8, i8); 16] = [
+    (1, 1),
+    (-1, -3),
+    (-3, 2),
+    (4, -1),
+    (-5, -2),
+    (2, 5),
+    (5, 3),
+    (3, -5),
+    (-2, 6),
+    (0, -7),
+    (-4, -6),
+    (-6, 4),
+    (-8, 0),
+    (7, -4),
+    (6, 7),
+    (-7, -8),
+];
+
+/// Filter used to shrink a supersampled image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Resample {
+    /// Average of each block of pixels.
+    Box,
+    /// Windowed sinc with the given number of lobes, at least one, scaled to the downsampling
+    /// factor.
+    Lanczos(usize),
+}
+
+/// Supported numbers of coverage and depth samples per pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Samples {
+    /// No multisampling.
+    #[default]
+    One,
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl Samples {
+    pub fn count(self) -> usize {
+        match self {
+            Samples::One => 1,
+            Samples::Two => 2,
+            Samples::Four => 4,
+            Samples::Eight => 8,
+            Samples::Sixteen => 16,
+        }
+    }
+}
+
+/// Offsets of each sample from the centre of its pixel.
+pub fn sample_offsets(samples: Samples) -> Vec<Vec2d> {
+    let pattern: &[(i8, i8)] = match samples {
+        Samples::One => &[(0, 0)],
+        Samples::Two => &PATTERN_2,
+        Samples::Four => &PATTERN_4,
+        Samples::Eight => &PATTERN_8,
+        Samples::Sixteen => &PATTERN_16,
+    };
+
+    pattern
+        .iter()
+        .map(|&(x, y)| [x as f64 / 16., y as f64 / 16.])
+        .collect()
+}
+
+fn lanczos(x: f64, lobes: f64) -> f64 {
+    let sinc = |x: f64| {
+        if x == 0. {
+            1.
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    };
+
+    if x.abs() < lobes {
+        sinc(x) * sinc(x / lobes)
+    } else {
+        0.
+    }
+}
+
+/// Weights of the source pixels contributing to each of `size / factor` destination pixels along
+/// one axis, normalised to sum to 1.
+fn resample_weights(size: usize, factor: usize, filter: Resample) -> Vec<Vec<(usize, f64)>> {
+    (0..size / factor)
+        .map(|destination| {
+            let weights: Vec<(usize, f64)> = match filter {
+                Resample::Box => (destination * factor..(destination + 1) * factor)
+                    .map(|source| (source, 1.))
+                    .collect(),
+                Resample::Lanczos(lobes) => {
+                    let centre: f64 = (destination as f64 + 0.5) * factor as f64;
+                    let radius: f64 = (lobes * factor) as f64;
+                    let first: usize = (centre - radius).floor().max(0.) as usize;
+                    let last: usize = ((centre + radius).ceil() as usize).min(size);
+
+                    (first..last)
+                        .map(|source| {
+                            let distance = (source as f64 + 0.5 - centre) / factor as f64;
+                            (source, lanczos(distance, lobes as f64))
+                        })
+                        .collect()
+                }
+            };
+
+            let total: f64 = weights.iter().map(|(_, w)| w).sum();
+            weights.into_iter().map(|(s, w)| (s, w / total)).collect()
+        })
+        .collect()
+}
+
+/// Per-sample colours and depths of a multisampled image.
+pub(super) struct Multisample {
+    samples: Samples,
+    count: usize,
+    offsets: Vec<Fixed>,
+    colour: Vec<Pixel>,
+    depth: DepthBuffer,
+}
+
+impl Multisample {
+    /// Blend `source` into the samples of pixel `index` set in `coverage`.
+    pub(super) fn write(&mut self, index: usize, coverage: u32, source: Pixel, blend: Blend) {
+        for sample in (0..self.count).filter(|s| coverage & (1 << s) != 0) {
+            let stored: &mut Pixel = &mut self.colour[index * self.count + sample];
+            *stored = blend.apply(source, *stored);
+        }
+    }
+
+    pub(super) fn fill(&mut self, index: usize, pixel: Pixel) {
+        self.colour[index * self.count..(index + 1) * self.count].fill(pixel);
+    }
+
+    /// Colours and depths of every sample, `count` of each per pixel in turn.
+    pub(super) fn buffers(&self) -> (&[Pixel], &[f64]) {
+        (&self.colour, self.depth.values())
+    }
+
+    pub(super) fn buffers_mut(&mut self) -> (&mut [Pixel], &mut [f64]) {
+        (&mut self.colour, self.depth.values_mut())
+    }
+
+    pub(super) fn clear(&mut self, pixel: Pixel) {
+        self.colour.fill(pixel);
+        self.depth.clear();
+    }
+}
+
+impl Image {
+    pub fn samples(&self) -> Samples {
+        self.multisample
+            .as_ref()
+            .map_or(Samples::One, |m| m.samples)
+    }
+
+    /// Render with `samples` coverage and depth samples per pixel, shading once per pixel, or
+    /// without multisampling for `Samples::One`. Samples start from the current pixels, and take
+    /// the depth buffer's comparison and clear value.
+    pub fn set_samples(&mut self, samples: Samples) {
+        let offsets: Vec<Fixed> = sample_offsets(samples).iter().map(raster::snap).collect();
+        let count: usize = samples.count();
+        if count == 1 {
+            self.multisample = None;
+            return;
+        }
+
+        let depth: DepthBuffer = self.zbuffer.resized(self.width * count, self.height);
+        self.multisample = Some(Multisample {
+            samples,
+            count,
+            offsets,
+            colour: self
+                .data
+                .iter()
+                .flat_map(|&p| std::iter::repeat_n(p, count))
+                .collect(),
+            depth,
+        });
+    }
+
+    /// Average each pixel's samples into the image, weighting colours by their alpha, and keep
+    /// the nearest of their depths in the depth buffer.
+    pub fn resolve(&mut self) {
+        let Some(multisample) = &self.multisample else {
+            return;
+        };
+
+        for (index, depths) in multisample
+            .depth
+            .values()
+            .chunks(multisample.count)
+            .enumerate()
+        {
+            for &depth in depths {
+                self.zbuffer.test_and_set(index, depth);
+            }
+        }
+        self.hiz.invalidate();
+
+        for (pixel, samples) in self
+            .data
+            .iter_mut()
+            .zip(multisample.colour.chunks(multisample.count))
+        {
+            let total: Vec4d = samples.iter().fold([0.; 4], |total, &sample| {
+                let [red, green, blue, alpha] = Vec4d::from(sample);
+                add(&total, &[red * alpha, green * alpha, blue * alpha, alpha])
+            });
+
+            let [red, green, blue, alpha] = total;
+            let rgb: Vec3d = match alpha > 0. {
+                true => scalar_mul(&[red, green, blue], 1. / alpha),
+                false => [0.; 3],
+            };
+            *pixel = [rgb[0], rgb[1], rgb[2], alpha / multisample.count as f64].into();
+        }
+    }
+
+    /// Shrink the image by `factor` in each direction with `filter`, to resolve a render made at
+    /// a multiple of the final resolution. Panics if `factor` or the number of Lanczos lobes is
+    /// zero.
+    pub fn downsample(&self, factor: usize, filter: Resample) -> Image {
+        assert!(factor > 0, "Downsampling factor must be at least 1");
+        assert!(
+            filter != Resample::Lanczos(0),
+            "Lanczos filters need at least one lobe"
+        );
+        let columns = resample_weights(self.width, factor, filter);
+        let rows = resample_weights(self.height, factor, filter);
+        let (width, height) = (columns.len(), rows.len());
+
+        let horizontal: Vec<Vec4d> = (0..self.height)
+            .flat_map(|y| {
+                columns.iter().map(move |weights| {
+                    weights.iter().fold([0.; 4], |total, &(x, w)| {
+                        add(
+                            &total,
+                            &scalar_mul(&self.data[x + self.width * y].into(), w),
+                        )
+                    })
+                })
+            })
+            .collect();
+
+        let mut image = Image::blank(width, height);
+        image.origin = self.origin;
+        image.channels = self.channels;
+        for (y, weights) in rows.iter().enumerate() {
+            for x in 0..width {
+                let colour: Vec4d = weights.iter().fold([0.; 4], |total, &(source, w)| {
+                    add(&total, &scalar_mul(&horizontal[x + width * source], w))
+                });
+                image.data[x + width * y] = colour.into();
+            }
+        }
+
+        image
+    }
+
+    /// Multisampled counterpart of `rasterise`, testing coverage and depth at every sample and
+    /// calling `write` once per pixel with the barycentric weights of its centre and the mask of
+    /// samples that passed, the weights being mapped by `map` as `rasterise` does.
+    pub(super) fn rasterise_multisample<M, W>(&mut self, setup: &Setup, map: M, mut write: W)
+    where
+        M: Fn(Vec3d) -> Vec3d,
+        W: FnMut(&mut Self, usize, Vec3d, u32),
+    {
+        let Some(bounds) = setup.bounds(self.width, self.height) else {
+            return;
+        };
+        let Some(multisample) = &mut self.multisample else {
+            return;
+        };
+        let (width, height) = (self.width, self.height);
+
+        let mut covered: Vec<(usize, Vec3d, u32)> = Vec::new();
+        setup.touched_pixels(bounds, |x, y, values| {
+            let index: usize = x + width * (height - y - 1);
+            let centre: Fixed = raster::centre(x, y);
+
+            let mut coverage: u32 = 0;
+            for (sample, offset) in multisample.offsets.iter().enumerate() {
+                let values: [i64; 3] =
+                    setup.edge_values(&[centre[0] + offset[0], centre[1] + offset[1]]);
+                if setup.inside(&values) {
+                    let depth: f64 = setup.depth(&setup.weights(&values));
+                    if multisample
+                        .depth
+                        .test_and_set(index * multisample.count + sample, depth)
+                    {
+                        coverage |= 1 << sample;
+                    }
+                }
+            }
+
+            if coverage != 0 {
+                covered.push((index, setup.weights(values), coverage));
+            }
+        });
+
+        for (index, weights, coverage) in covered {
+            let weights: Vec3d = map(weights);
+            self.record_hit(index, weights);
+            write(self, index, weights, coverage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vertex;
+    use crate::image::Position;
+    use crate::image::pixel::{BLUE, RED, WHITE};
+
+    fn triangle(image: &mut Image, corners: [[f64; 3]; 3], colour: Pixel) {
+        let [i, j, k] = corners.map(Vertex::from);
+        image.shaded_triangle(&i, &j, &k, |_| colour);
+    }
+
+    #[test]
+    fn sample_patterns_lie_within_the_pixel() {
+        for samples in [
+            Samples::One,
+            Samples::Two,
+            Samples::Four,
+            Samples::Eight,
+            Samples::Sixteen,
+        ] {
+            let offsets: Vec<Vec2d> = sample_offsets(samples);
+            assert_eq!(offsets.len(), samples.count());
+            assert!(offsets.iter().flatten().all(|o| o.abs() <= 0.5));
+        }
+    }
+
+    #[test]
+    fn msaa_blends_partially_covered_edges() {
+        let mut image = Image::blank(4, 4);
+        image.set_samples(Samples::Four);
+        image.clear(BLUE);
+
+        // Diagonal edge from the bottom right to the top left corner
+        triangle(
+            &mut image,
+            [[-1., -1., 0.], [1., -1., 0.], [1., 1., 0.]],
+            RED,
+        );
+        image.resolve();
+
+        let interior: Pixel = image.get(&Position { x: 3, y: 0 }).unwrap();
+        let edge: Pixel = image.get(&Position { x: 1, y: 1 }).unwrap();
+        assert_eq!(interior, RED);
+        assert!(edge.red > 0 && edge.blue > 0, "{edge:?}");
+        assert_eq!(image.get(&Position { x: 0, y: 3 }), Some(BLUE));
+    }
+
+    #[test]
+    fn msaa_depth_is_tested_per_sample() {
+        let mut image = Image::blank(2, 2);
+        image.set_samples(Samples::Eight);
+
+        triangle(
+            &mut image,
+            [[-1., -1., 0.5], [3., -1., 0.5], [-1., 3., 0.5]],
+            WHITE,
+        );
+        triangle(
+            &mut image,
+            [[-1., -1., 0.], [3., -1., 0.], [-1., 3., 0.]],
+            RED,
+        );
+        image.resolve();
+
+        assert!(image.data.iter().all(|&p| p == WHITE));
+    }
+
+    #[test]
+    fn downsample_filters() {
+        let mut image = Image::blank(4, 2);
+        for x in 0..4 {
+            for y in 0..2 {
+                let colour: Pixel = if (x + y) % 2 == 0 {
+                    WHITE
+                } else {
+                    [0.; 3].into()
+                };
+                image.set(colour, &Position { x, y });
+            }
+        }
+
+        let boxed: Image = image.downsample(2, Resample::Box);
+        assert_eq!((boxed.width, boxed.height), (2, 1));
+        assert!(boxed.data.iter().all(|&p| p == [127.5; 3].into()));
+
+        let mut flat = Image::blank(8, 8);
+        flat.clear(RED);
+        let lanczos: Image = flat.downsample(2, Resample::Lanczos(3));
+        assert_eq!((lanczos.width, lanczos.height), (4, 4));
+        assert!(lanczos.data.iter().all(|&p| p == RED));
+    }
+
+    #[test]
+    #[should_panic(expected = "factor must be at least 1")]
+    fn downsampling_by_zero_panics() {
+        Image::blank(4, 4).downsample(0, Resample::Box);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one lobe")]
+    fn lanczos_without_lobes_panics() {
+        Image::blank(4, 4).downsample(2, Resample::Lanczos(0));
+    }
+}
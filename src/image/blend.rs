@@ -0,0 +1,102 @@
+use crate::image::pixel::Pixel;
+use crate::math::vector::{Vec3d, Vec4d, add, mul, scalar_mul};
+
+/// How a fragment's colour is combined with the pixel already in the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Blend {
+    /// Overwrite the pixel, alpha included.
+    Replace,
+    /// Porter-Duff source over destination, with straight alpha.
+    AlphaOver,
+    /// Source over destination, with the source colour already multiplied by its alpha.
+    Premultiplied,
+    /// Add the source colour, weighted by its alpha.
+    Additive,
+    /// Tint the destination by the source colour, as far as the source alpha covers it.
+    Multiply,
+}
+
+impl Blend {
+    pub fn apply(self, source: Pixel, destination: Pixel) -> Pixel {
+        let [sr, sg, sb, sa] = Vec4d::from(source).map(|c| c / 255.);
+        let [dr, dg, db, da] = Vec4d::from(destination).map(|c| c / 255.);
+
+        let colour: Vec3d = [sr, sg, sb];
+        let premultiplied: Vec3d = match self {
+            Blend::Premultiplied => colour,
+            _ => scalar_mul(&colour, sa),
+        };
+        let below: Vec3d = scalar_mul(&[dr, dg, db], da);
+
+        let (rgb, alpha): (Vec3d, f64) = match self {
+            Blend::Replace => return source,
+            Blend::AlphaOver | Blend::Premultiplied => (
+                add(&premultiplied, &scalar_mul(&below, 1. - sa)),
+                sa + da * (1. - sa),
+            ),
+            Blend::Additive => (add(&below, &premultiplied), (sa + da).min(1.)),
+            Blend::Multiply => (
+                mul(&below, &add(&scalar_mul(&colour, sa), &[1. - sa; 3])),
+                da,
+            ),
+        };
+
+        // Back to straight alpha for storage
+        let rgb: Vec3d = match alpha > 0. {
+            true => scalar_mul(&rgb, 1. / alpha),
+            false => [0.; 3],
+        };
+        let [red, green, blue] = rgb;
+        scalar_mul(&[red, green, blue, alpha], 255.).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::pixel::{BLUE, TRANSPARENT, WHITE};
+
+    fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Pixel {
+        Pixel {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    #[test]
+    fn blend_modes() {
+        let half_red: Pixel = rgba(255, 0, 0, 128);
+
+        assert_eq!(Blend::Replace.apply(half_red, BLUE), half_red);
+        assert_eq!(
+            Blend::AlphaOver.apply(half_red, BLUE),
+            rgba(128, 0, 127, 255)
+        );
+        assert_eq!(
+            Blend::Premultiplied.apply(rgba(128, 0, 0, 128), BLUE),
+            rgba(128, 0, 127, 255)
+        );
+        assert_eq!(
+            Blend::Additive.apply(half_red, BLUE),
+            rgba(128, 0, 255, 255)
+        );
+        assert_eq!(
+            Blend::Multiply.apply(half_red, WHITE),
+            rgba(255, 127, 127, 255)
+        );
+    }
+
+    #[test]
+    fn over_transparent_keeps_source() {
+        let half_red: Pixel = rgba(255, 0, 0, 128);
+
+        assert_eq!(Blend::AlphaOver.apply(half_red, TRANSPARENT), half_red);
+        assert_eq!(
+            Blend::AlphaOver.apply(TRANSPARENT, TRANSPARENT),
+            TRANSPARENT
+        );
+        assert_eq!(Blend::Multiply.apply(half_red, TRANSPARENT), TRANSPARENT);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::image::netpbm::{Encoding, Format, Netpbm};
+use crate::image::pixel::Pixel;
+use crate::image::raster::{BLOCK_SIZE, Block};
+use crate::math::vector::{Vec3d, add, scalar_mul};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compare {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+}
+
+impl Compare {
+    /// Whether an incoming `depth` passes against the `stored` one.
+    pub fn test(self, depth: f64, stored: f64) -> bool {
+        match self {
+            Compare::Less => depth < stored,
+            Compare::LessEqual => depth <= stored,
+            Compare::Greater => depth > stored,
+            Compare::GreaterEqual => depth >= stored,
+            Compare::Always => true,
+        }
+    }
+
+    /// Comparison giving the same ordering once depths are reversed.
+    fn mirrored(self) -> Self {
+        match self {
+            Compare::Less => Compare::Greater,
+            Compare::LessEqual => Compare::GreaterEqual,
+            Compare::Greater => Compare::Less,
+            Compare::GreaterEqual => Compare::LessEqual,
+            Compare::Always => Compare::Always,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColourMap {
+    Viridis,
+    Turbo,
+}
+
+impl ColourMap {
+    /// Colour of `t` in `[0, 1]`, from polynomial fits of the published maps.
+    pub fn colour(self, t: f64) -> Vec3d {
+        let t: f64 = t.clamp(0., 1.);
+        let coefficients: &[Vec3d] = match self {
+            ColourMap::Viridis => &[
+                [0.277727327, 0.005407345, 0.334099805],
+                [0.105093043, 1.404613530, 1.384590163],
+                [-0.330861829, 0.214847559, 0.095095163],
+                [-4.634230499, -5.799100973, -19.332440956],
+                [6.228269936, 14.179933367, 56.690552601],
+                [4.776384998, -13.745145378, -65.353032633],
+                [-5.435455856, 4.645852612, 26.312435250],
+            ],
+            ColourMap::Turbo => &[
+                [0.135721380, 0.091402610, 0.106673300],
+                [4.615392600, 2.194188390, 12.641946080],
+                [-42.660322580, 4.842966580, -60.582048360],
+                [132.131082340, -14.185033330, 110.362767710],
+                [-152.942393960, 4.277298570, -89.903109120],
+                [59.286379430, 2.829566040, 27.348249730],
+            ],
+        };
+
+        coefficients
+            .iter()
+            .rev()
+            .fold([0.; 3], |acc, c| add(&scalar_mul(&acc, t), c))
+            .map(|channel| channel.clamp(0., 1.))
+    }
+}
+
+/// Per-pixel depth in `[0, 1]`, where 0 is nearest to the viewer unless depth is reversed.
+pub struct DepthBuffer {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+    compare: Compare,
+    clear_value: f64,
+    reversed: bool,
+}
+
+impl DepthBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            values: vec![1.; width * height],
+            compare: Compare::Less,
+            clear_value: 1.,
+            reversed: false,
+        }
+    }
+
+    /// Cleared buffer of another size, with the same comparison, clear value and direction.
+    pub(crate) fn resized(&self, width: usize, height: usize) -> Self {
+        let mut resized = Self::new(width, height);
+        resized.set_reversed(self.reversed);
+        resized.set_compare(self.compare);
+        resized.set_clear_value(self.clear_value);
+        resized
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Depths in the same row order as the image's pixels.
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub(crate) fn values_mut(&mut self) -> &mut [f64] {
+        &mut self.values
+    }
+
+    pub fn compare(&self) -> Compare {
+        self.compare
+    }
+
+    pub fn set_compare(&mut self, compare: Compare) {
+        self.compare = compare;
+    }
+
+    pub fn clear_value(&self) -> f64 {
+        self.clear_value
+    }
+
+    /// Set the value depths are reset to, and clear the buffer with it.
+    pub fn set_clear_value(&mut self, clear_value: f64) {
+        self.clear_value = clear_value;
+        self.clear();
+    }
+
+    pub fn reversed(&self) -> bool {
+        self.reversed
+    }
+
+    /// Switch between 0 and 1 being nearest to the viewer, mirroring the comparison and clear
+    /// value so that the same surfaces stay visible, and clearing the buffer.
+    pub fn set_reversed(&mut self, reversed: bool) {
+        if reversed != self.reversed {
+            self.reversed = reversed;
+            self.compare = self.compare.mirrored();
+            self.clear_value = 1. - self.clear_value;
+        }
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill(self.clear_value);
+    }
+
+    /// Depth of a projected vertex coordinate `z`, which runs from -1 at the back to 1 at the front.
+    pub fn depth_of(&self, z: f64) -> f64 {
+        if self.reversed {
+            (1. + z) / 2.
+        } else {
+            (1. - z) / 2.
+        }
+    }
+
+    /// 16-bit binary PGM, with depth scaled to the full range of the format.
+    pub fn pgm(&self) -> Vec<u8> {
+        Netpbm::from(self).encode(Format::Pgm(Encoding::Binary))
+    }
+
+    /// Greyscale little-endian PFM, whose rows run from the bottom of the image to the top.
+    pub fn pfm(&self) -> Vec<u8> {
+        let mut pfm = Vec::new();
+
+        pfm.extend_from_slice(b"Pf\n");
+        pfm.extend_from_slice(format!("{} {}\n", self.width, self.height).as_bytes());
+        pfm.extend_from_slice(b"-1.0\n");
+
+        for row in self.values.chunks(self.width.max(1)).rev() {
+            for depth in row {
+                pfm.extend_from_slice(&(*depth as f32).to_le_bytes());
+            }
+        }
+
+        pfm
+    }
+
+    /// PPM with the range of depths written to, mapped through `map` so that the nearest depth
+    /// takes the high end of the map, at 1. Pixels still holding the clear value are black.
+    pub fn colour_mapped_ppm(&self, map: ColourMap) -> Vec<u8> {
+        let written = || self.values.iter().filter(|&&d| d != self.clear_value);
+        let min: f64 = written().copied().fold(f64::INFINITY, f64::min);
+        let max: f64 = written().copied().fold(f64::NEG_INFINITY, f64::max);
+        let range: f64 = if max > min { max - min } else { 1. };
+
+        let mut ppm = Vec::new();
+
+        ppm.extend_from_slice(b"P6\n");
+        ppm.extend_from_slice(format!("{} {}\n", self.width, self.height).as_bytes());
+        ppm.extend_from_slice(b"255\n");
+
+        for &depth in &self.values {
+            if depth == self.clear_value {
+                ppm.extend_from_slice(&[0, 0, 0]);
+                continue;
+            }
+
+            let t: f64 = (depth - min) / range;
+            let t: f64 = if self.reversed { t } else { 1. - t };
+            let pixel: Pixel = scalar_mul(&map.colour(t), 255.).into();
+            ppm.extend_from_slice(&[pixel.red, pixel.green, pixel.blue]);
+        }
+
+        ppm
+    }
+
+    pub(crate) fn get(&self, index: usize) -> f64 {
+        self.values[index]
+    }
+
+    /// Store `depth` at `index` if it passes the comparison, returning whether it did.
+    pub(crate) fn test_and_set(&mut self, index: usize, depth: f64) -> bool {
+        let passed: bool = self.compare.test(depth, self.values[index]);
+        if passed {
+            self.values[index] = depth;
+        }
+        passed
+    }
+}
+
+/// Least and greatest depth within each block of a depth buffer's `BLOCK_SIZE` grid, with
+/// blocks counted from the bottom left as the rasteriser counts them. Used to skip whole blocks
+/// of a triangle that cannot pass the depth test anywhere in them.
+pub(crate) struct HierarchicalZ {
+    columns: usize,
+    /// Range of each block, or `None` when it must be worked out again from the depth buffer.
+    ranges: Vec<Option<[f64; 2]>>,
+}
+
+impl HierarchicalZ {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        let columns: usize = width.div_ceil(BLOCK_SIZE);
+        Self {
+            columns,
+            ranges: vec![None; columns * height.div_ceil(BLOCK_SIZE)],
+        }
+    }
+
+    /// Forget every range, after the depth buffer changes other than through `touch`.
+    pub(crate) fn invalidate(&mut self) {
+        self.ranges.fill(None);
+    }
+
+    /// Forget the range of the block holding the pixel at `x` and `y`, after writing its depth.
+    pub(crate) fn touch(&mut self, x: usize, y: usize) {
+        self.ranges[x / BLOCK_SIZE + self.columns * (y / BLOCK_SIZE)] = None;
+    }
+
+    /// Whether depths within `depth` can fail the depth test of `buffer` everywhere in `block`.
+    pub(crate) fn occludes(&mut self, buffer: &DepthBuffer, block: &Block) -> bool {
+        let [x, y] = [block.columns[0], block.rows[0]].map(|start| start / BLOCK_SIZE);
+        let range: &mut Option<[f64; 2]> = &mut self.ranges[x + self.columns * y];
+        let [least, greatest] = *range.get_or_insert_with(|| {
+            let columns = x * BLOCK_SIZE..((x + 1) * BLOCK_SIZE).min(buffer.width);
+            let rows = y * BLOCK_SIZE..((y + 1) * BLOCK_SIZE).min(buffer.height);
+            rows.flat_map(|row| {
+                let start: usize = buffer.width * (buffer.height - row - 1);
+                buffer.values[start + columns.start..start + columns.end].iter()
+            })
+            .fold([f64::INFINITY, f64::NEG_INFINITY], |[min, max], &d| {
+                [min.min(d), max.max(d)]
+            })
+        });
+
+        // The triangle's most favourable depth against the block's least demanding one
+        let [near, far] = block.depth;
+        match buffer.compare {
+            Compare::Less | Compare::LessEqual => !buffer.compare.test(near, greatest),
+            Compare::Greater | Compare::GreaterEqual => !buffer.compare.test(far, least),
+            Compare::Always => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_functions() {
+        let outcomes = |compare: Compare| [0.4, 0.5, 0.6].map(|depth| compare.test(depth, 0.5));
+
+        assert_eq!(outcomes(Compare::Less), [true, false, false]);
+        assert_eq!(outcomes(Compare::LessEqual), [true, true, false]);
+        assert_eq!(outcomes(Compare::Greater), [false, false, true]);
+        assert_eq!(outcomes(Compare::GreaterEqual), [false, true, true]);
+        assert_eq!(outcomes(Compare::Always), [true, true, true]);
+    }
+
+    #[test]
+    fn nearest_depth_wins() {
+        for reversed in [false, true] {
+            let mut buffer = DepthBuffer::new(1, 1);
+            buffer.set_reversed(reversed);
+
+            let (near, far) = (buffer.depth_of(0.5), buffer.depth_of(-0.5));
+            assert!(buffer.test_and_set(0, far));
+            assert!(buffer.test_and_set(0, near));
+            assert!(!buffer.test_and_set(0, far));
+            assert_eq!(buffer.get(0), near);
+        }
+    }
+
+    #[test]
+    fn hierarchical_z_rejects_hidden_blocks() {
+        use crate::image::raster::Setup;
+
+        // Full-screen triangle at depth 0.5 against a 16 by 16 buffer, whose bottom left block is
+        // filled nearer
+        let blocks = |depth: f64| {
+            let setup = Setup::new([[-20., -20.], [60., -20.], [-20., 60.]], [depth; 3]).unwrap();
+            let mut blocks: Vec<Block> = Vec::new();
+            setup.blocks([[0, 15], [0, 15]], |block| blocks.push(*block));
+            blocks
+        };
+
+        for reversed in [false, true] {
+            let mut buffer = DepthBuffer::new(16, 16);
+            buffer.set_reversed(reversed);
+            let mut hiz = HierarchicalZ::new(16, 16);
+
+            let (near, middle) = (buffer.depth_of(0.5), buffer.depth_of(0.));
+            for row in 8..16 {
+                buffer.values[16 * row..16 * row + 8].fill(near);
+            }
+
+            let occluded: Vec<bool> = blocks(middle)
+                .iter()
+                .map(|block| hiz.occludes(&buffer, block))
+                .collect();
+            assert_eq!(occluded, [true, false, false, false], "reversed {reversed}");
+
+            // Stale ranges are only forgotten when told
+            buffer.clear();
+            assert!(hiz.occludes(&buffer, &blocks(middle)[0]));
+            hiz.touch(3, 3);
+            assert!(!hiz.occludes(&buffer, &blocks(middle)[0]));
+        }
+    }
+
+    #[test]
+    fn colour_map_endpoints() {
+        let rounded = |map: ColourMap, t: f64| map.colour(t).map(|c| (c * 100.).round() / 100.);
+
+        assert_eq!(rounded(ColourMap::Viridis, 0.), [0.28, 0.01, 0.33]);
+        assert_eq!(rounded(ColourMap::Viridis, 1.), [0.99, 0.91, 0.13]);
+        assert_eq!(rounded(ColourMap::Turbo, 0.), [0.14, 0.09, 0.11]);
+        assert_eq!(rounded(ColourMap::Turbo, 1.), [0.57, 0.05, 0.]);
+    }
+
+    #[test]
+    fn export_formats() {
+        let mut buffer = DepthBuffer::new(2, 2);
+        buffer.values = vec![0., 0.5, 1., 0.25];
+
+        let pgm: Vec<u8> = buffer.pgm();
+        assert!(pgm.starts_with(b"P5\n2 2\n65535\n"));
+        assert_eq!(pgm[pgm.len() - 8..], [0, 0, 128, 0, 255, 255, 64, 0]);
+
+        let pfm: Vec<u8> = buffer.pfm();
+        let header: &[u8] = b"Pf\n2 2\n-1.0\n";
+        assert!(pfm.starts_with(header));
+        let first: [u8; 4] = pfm[header.len()..header.len() + 4].try_into().unwrap();
+        assert_eq!(f32::from_le_bytes(first), 1.);
+
+        let ppm: Vec<u8> = buffer.colour_mapped_ppm(ColourMap::Turbo);
+        let header: &[u8] = b"P6\n2 2\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm[header.len() + 6..header.len() + 9], [0, 0, 0]);
+        assert_eq!(
+            ppm[header.len()..header.len() + 3],
+            [144, 13, 0],
+            "Nearest depth should take the high end of the map"
+        );
+    }
+
+    #[test]
+    fn clear_value() {
+        let mut buffer = DepthBuffer::new(2, 1);
+        buffer.set_clear_value(0.25);
+        assert_eq!(buffer.values(), [0.25, 0.25]);
+
+        buffer.set_reversed(true);
+        assert_eq!(buffer.compare(), Compare::Greater);
+        assert_eq!(buffer.values(), [0.75, 0.75]);
+    }
+}
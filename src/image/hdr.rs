@@ -0,0 +1,271 @@
+use crate::image::pixel::{Pixel, linear_to_srgb};
+use crate::math::vector::{Vec3d, scalar_mul};
+
+/// Operator compressing unbounded radiance into `[0, 1]` for display.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Scale by the exposure and clip.
+    Clamp,
+    /// `x / (1 + x)`, applied to each channel.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+impl ToneMap {
+    /// Map linear `radiance`, first scaled by `2^exposure`.
+    pub fn apply(self, radiance: &Vec3d, exposure: f64) -> Vec3d {
+        let exposed: Vec3d = scalar_mul(radiance, exposure.exp2());
+
+        exposed.map(|x| {
+            let x: f64 = x.max(0.);
+            let mapped: f64 = match self {
+                ToneMap::Clamp => x,
+                ToneMap::Reinhard => x / (1. + x),
+                ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            };
+            mapped.clamp(0., 1.)
+        })
+    }
+}
+
+/// Encoding applied to tone mapped values before they are quantised to 8 bits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transfer {
+    Srgb,
+    /// Raise to `1 / gamma`.
+    Gamma(f64),
+}
+
+impl Transfer {
+    pub fn encode(self, channel: f64) -> f64 {
+        match self {
+            Transfer::Srgb => linear_to_srgb(channel),
+            Transfer::Gamma(gamma) => channel.powf(1. / gamma),
+        }
+    }
+}
+
+/// Linear RGB radiance per pixel, in the same row order as the image's pixels.
+pub struct HdrBuffer {
+    width: usize,
+    height: usize,
+    values: Vec<[f32; 3]>,
+}
+
+/// Brightest value RGBE can hold, with the largest mantissa and exponent.
+const RGBE_MAX: f32 = 255. * (1u128 << 119) as f32;
+
+/// Radiance's shared-exponent encoding of a colour. Channels brighter than it can hold, infinite
+/// ones included, are clamped to the brightest value it can.
+fn rgbe(colour: [f32; 3]) -> [u8; 4] {
+    let [red, green, blue] = colour.map(|c| c.clamp(0., RGBE_MAX));
+    let max: f32 = red.max(green).max(blue);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+
+    // Exponent such that `max / 2^exponent` lies in [0.5, 1)
+    let mut exponent: i32 = max.log2().floor() as i32 + 1;
+    if max / (exponent as f32).exp2() >= 1. {
+        exponent += 1;
+    }
+    let exponent: i32 = exponent.clamp(-128, 127);
+    let scale: f32 = 256. / (exponent as f32).exp2();
+
+    [
+        (red * scale) as u8,
+        (green * scale) as u8,
+        (blue * scale) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+/// OpenEXR header attribute.
+fn attribute(exr: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for text in [name, kind] {
+        exr.extend_from_slice(text.as_bytes());
+        exr.push(0);
+    }
+    exr.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    exr.extend_from_slice(value);
+}
+
+impl HdrBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            values: vec![[0.; 3]; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn values(&self) -> &[[f32; 3]] {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill([0.; 3]);
+    }
+
+    pub(crate) fn set(&mut self, index: usize, radiance: &Vec3d) {
+        self.values[index] = radiance.map(|channel| channel as f32);
+    }
+
+    /// Tone map and encode every value into an opaque pixel.
+    pub fn resolve(&self, tone_map: ToneMap, exposure: f64, transfer: Transfer) -> Vec<Pixel> {
+        self.values
+            .iter()
+            .map(|value| {
+                let mapped: Vec3d = tone_map.apply(&value.map(f64::from), exposure);
+                mapped.map(|channel| transfer.encode(channel) * 255.).into()
+            })
+            .collect()
+    }
+
+    /// Radiance `.hdr` file of uncompressed RGBE pixels, with rows from the top down.
+    pub fn radiance_hdr(&self) -> Vec<u8> {
+        let mut hdr = Vec::new();
+
+        hdr.extend_from_slice(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n");
+        hdr.extend_from_slice(format!("-Y {} +X {}\n", self.height, self.width).as_bytes());
+        for value in &self.values {
+            hdr.extend_from_slice(&rgbe(*value));
+        }
+
+        hdr
+    }
+
+    /// Single-part scanline OpenEXR file holding uncompressed 32-bit float R, G and B channels.
+    pub fn exr(&self) -> Vec<u8> {
+        let mut exr: Vec<u8> = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+        // Channels are listed, and stored within each line, in alphabetical order
+        let mut channels: Vec<u8> = Vec::new();
+        for name in [b'B', b'G', b'R'] {
+            channels.extend_from_slice(&[name, 0]);
+            // Float pixels, not perceptually linear, and sampled at every pixel
+            channels.extend_from_slice(&2i32.to_le_bytes());
+            channels.extend_from_slice(&[0, 0, 0, 0]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+
+        attribute(&mut exr, "channels", "chlist", &channels);
+        attribute(&mut exr, "compression", "compression", &[0]);
+        attribute(&mut exr, "dataWindow", "box2i", &window);
+        attribute(&mut exr, "displayWindow", "box2i", &window);
+        attribute(&mut exr, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut exr, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut exr, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut exr, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        exr.push(0);
+
+        // Offsets of each line's block, which follow the table
+        let line_size: usize = 8 + 12 * self.width;
+        let table_end: usize = exr.len() + 8 * self.height;
+        for y in 0..self.height {
+            exr.extend_from_slice(&((table_end + y * line_size) as u64).to_le_bytes());
+        }
+
+        for (y, row) in self.values.chunks(self.width.max(1)).enumerate() {
+            exr.extend_from_slice(&(y as i32).to_le_bytes());
+            exr.extend_from_slice(&((12 * self.width) as i32).to_le_bytes());
+            for channel in [2, 1, 0] {
+                for value in row {
+                    exr.extend_from_slice(&value[channel].to_le_bytes());
+                }
+            }
+        }
+
+        exr
+    }
+}
+
+/// Decode an RGBE value, the inverse of the encoding written by `HdrBuffer::radiance_hdr`.
+pub fn rgbe_to_radiance([red, green, blue, exponent]: [u8; 4]) -> Vec3d {
+    if exponent == 0 {
+        return [0.; 3];
+    }
+
+    let scale: f64 = (exponent as f64 - 136.).exp2();
+    scalar_mul(&[red, green, blue].map(|c| c as f64 + 0.5), scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_map_operators() {
+        assert_eq!(ToneMap::Clamp.apply(&[0.25, 1., 4.], 1.), [0.5, 1., 1.]);
+        assert_eq!(ToneMap::Reinhard.apply(&[1., 3., 0.], 0.), [0.5, 0.75, 0.]);
+
+        let [black, mid, bright] = ToneMap::Aces.apply(&[0., 0.18, 1000.], 0.);
+        assert_eq!(black, 0.);
+        assert!(mid > 0.2 && mid < 0.3);
+        assert!(bright > 0.99 && bright <= 1.);
+    }
+
+    #[test]
+    fn transfer_functions() {
+        assert_eq!(Transfer::Gamma(2.).encode(0.25), 0.5);
+        assert!((Transfer::Srgb.encode(0.5) - 0.735).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rgbe_round_trip() {
+        for value in [[1., 0.5, 0.25], [1000., 3., 0.], [0.01, 0.02, 0.04]] {
+            // Precision is relative to the brightest channel, which sets the shared exponent
+            let tolerance: f64 = value.iter().fold(0_f64, |max, &v| max.max(v as f64)) / 128.;
+            let decoded: Vec3d = rgbe_to_radiance(rgbe(value));
+            for (d, v) in std::iter::zip(decoded, value) {
+                assert!((d - v as f64).abs() <= tolerance, "{value:?}");
+            }
+        }
+        assert_eq!(rgbe([0.; 3]), [0; 4]);
+
+        // Too bright to encode, rather than wrapping around to black
+        for bright in [f32::INFINITY, f32::MAX] {
+            assert_eq!(rgbe([bright, 1., -1.]), [255, 0, 0, 255]);
+        }
+        assert!(rgbe_to_radiance([255, 0, 0, 255])[0] >= RGBE_MAX as f64);
+    }
+
+    #[test]
+    fn export_formats() {
+        let mut buffer = HdrBuffer::new(3, 2);
+        buffer.set(0, &[2., 1., 0.5]);
+
+        let hdr: Vec<u8> = buffer.radiance_hdr();
+        let header: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 3\n";
+        assert!(hdr.starts_with(header));
+        assert_eq!(hdr.len(), header.len() + 4 * 6);
+        assert_eq!(hdr[header.len()..header.len() + 4], [128, 64, 32, 130]);
+
+        let exr: Vec<u8> = buffer.exr();
+        assert_eq!(exr[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // The first line's offset points at a block starting with its y coordinate and size,
+        // then blue, green and red for each pixel in turn
+        let table: usize = exr.len() - 2 * (8 + 12 * 3) - 16;
+        let first = u64::from_le_bytes(exr[table..table + 8].try_into().unwrap()) as usize;
+        assert_eq!(exr[first..first + 8], [0, 0, 0, 0, 36, 0, 0, 0]);
+        let red: &[u8] = &exr[first + 8 + 24..first + 8 + 28];
+        assert_eq!(f32::from_le_bytes(red.try_into().unwrap()), 2.);
+    }
+}
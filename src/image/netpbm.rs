@@ -0,0 +1,479 @@
+use std::io::{Error, ErrorKind};
+
+use crate::image::depth::DepthBuffer;
+use crate::image::pixel::Pixel;
+use crate::image::{Channels, Image};
+
+/// Longest line written in the plain formats, as the specification recommends.
+const MAX_LINE: usize = 70;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    /// Samples written as decimal text, as in P1, P2 and P3.
+    Ascii,
+    Binary,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// One bit per pixel, set for pixels darker than half of the maximum value.
+    Pbm(Encoding),
+    Pgm(Encoding),
+    Ppm(Encoding),
+    /// P7, keeping every channel including alpha.
+    Pam,
+}
+
+/// Samples of a Netpbm image, with rows from the top down and channels interleaved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Netpbm {
+    pub width: usize,
+    pub height: usize,
+    /// 1 for greyscale, 2 for greyscale with alpha, 3 for RGB and 4 for RGB with alpha.
+    pub channels: usize,
+    pub maxval: u16,
+    pub samples: Vec<u16>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the whitespace-separated tokens of a header, skipping `#` comments.
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&byte) = self.bytes.get(self.offset) {
+            if byte == b'#' {
+                while self.bytes.get(self.offset).is_some_and(|&b| b != b'\n') {
+                    self.offset += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.offset += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<&'a [u8], Error> {
+        self.skip_whitespace();
+        let start: usize = self.offset;
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            self.offset += 1;
+        }
+
+        match self.offset > start {
+            true => Ok(&self.bytes[start..self.offset]),
+            false => Err(invalid("Unexpected end of Netpbm data")),
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid("Invalid number in Netpbm data"))
+    }
+
+    /// The single digit of a plain PBM sample, which need not be separated from the next one.
+    fn bit(&mut self) -> Result<u16, Error> {
+        self.skip_whitespace();
+        let bit = match self.bytes.get(self.offset) {
+            Some(b'0') => 0,
+            Some(b'1') => 1,
+            _ => return Err(invalid("Invalid PBM sample")),
+        };
+        self.offset += 1;
+        Ok(bit)
+    }
+}
+
+/// Write samples as text, wrapping lines before they grow too long.
+fn write_ascii(out: &mut Vec<u8>, samples: impl Iterator<Item = u16>, separator: &str) {
+    let mut line: usize = 0;
+
+    for sample in samples {
+        let text: String = sample.to_string();
+        if line > 0 && line + separator.len() + text.len() > MAX_LINE {
+            out.push(b'\n');
+            line = 0;
+        } else if line > 0 {
+            out.extend_from_slice(separator.as_bytes());
+            line += separator.len();
+        }
+        out.extend_from_slice(text.as_bytes());
+        line += text.len();
+    }
+    out.push(b'\n');
+}
+
+/// Write samples as bytes, or big-endian pairs of bytes when `maxval` needs 16 bits.
+fn write_binary(out: &mut Vec<u8>, samples: impl Iterator<Item = u16>, maxval: u16) {
+    for sample in samples {
+        match maxval {
+            0..=255 => out.push(sample as u8),
+            _ => out.extend_from_slice(&sample.to_be_bytes()),
+        }
+    }
+}
+
+impl Netpbm {
+    fn pixels(&self) -> impl Iterator<Item = &[u16]> {
+        self.samples.chunks_exact(self.channels)
+    }
+
+    /// Rec. 709 luma of a pixel, or its grey level if it has no colour.
+    fn grey(&self, pixel: &[u16]) -> u16 {
+        match self.channels {
+            1 | 2 => pixel[0],
+            _ => (0.2126 * pixel[0] as f64 + 0.7152 * pixel[1] as f64 + 0.0722 * pixel[2] as f64)
+                .round() as u16,
+        }
+    }
+
+    fn colour(&self, pixel: &[u16]) -> [u16; 3] {
+        match self.channels {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        }
+    }
+
+    /// Encode as `format`, converting between colour and greyscale and dropping alpha as needed.
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let size: String = format!("{} {}\n", self.width, self.height);
+        let maxval: String = format!("{}\n", self.maxval);
+
+        match format {
+            Format::Pbm(encoding) => {
+                let bits = self
+                    .pixels()
+                    .map(|p| (2 * self.grey(p) as u32) < self.maxval as u32)
+                    .map(u16::from);
+                match encoding {
+                    Encoding::Ascii => {
+                        out.extend_from_slice(format!("P1\n{size}").as_bytes());
+                        write_ascii(&mut out, bits, "");
+                    }
+                    Encoding::Binary => {
+                        out.extend_from_slice(format!("P4\n{size}").as_bytes());
+                        let bits: Vec<u16> = bits.collect();
+                        for row in bits.chunks(self.width.max(1)) {
+                            for byte in row.chunks(8) {
+                                let packed = byte.iter().enumerate().map(|(i, &b)| b << (7 - i));
+                                out.push(packed.sum::<u16>() as u8);
+                            }
+                        }
+                    }
+                }
+            }
+            Format::Pgm(encoding) | Format::Ppm(encoding) => {
+                let (magic, samples): (u8, Vec<u16>) = match format {
+                    Format::Pgm(_) => (2, self.pixels().map(|p| self.grey(p)).collect()),
+                    _ => (3, self.pixels().flat_map(|p| self.colour(p)).collect()),
+                };
+
+                let magic: u8 = magic + 3 * (encoding == Encoding::Binary) as u8;
+                out.extend_from_slice(format!("P{magic}\n{size}{maxval}").as_bytes());
+                match encoding {
+                    Encoding::Ascii => write_ascii(&mut out, samples.into_iter(), " "),
+                    Encoding::Binary => write_binary(&mut out, samples.into_iter(), self.maxval),
+                }
+            }
+            Format::Pam => {
+                let tuple_type: &str = match (self.channels, self.maxval) {
+                    (1, 1) => "BLACKANDWHITE",
+                    (1, _) => "GRAYSCALE",
+                    (2, _) => "GRAYSCALE_ALPHA",
+                    (3, _) => "RGB",
+                    _ => "RGB_ALPHA",
+                };
+                out.extend_from_slice(
+                    format!(
+                        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {tuple_type}\nENDHDR\n",
+                        self.width, self.height, self.channels, self.maxval
+                    )
+                    .as_bytes(),
+                );
+                write_binary(&mut out, self.samples.iter().copied(), self.maxval);
+            }
+        }
+
+        out
+    }
+
+    /// Decode any of P1 to P7. PBM images become greyscale with a maximum value of 1, so that
+    /// black is 0 as in the other formats.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let magic: u8 = match bytes {
+            [b'P', digit @ b'1'..=b'7', ..] => digit - b'0',
+            _ => return Err(invalid("Missing Netpbm magic number")),
+        };
+        let mut tokens = Tokens { bytes, offset: 2 };
+
+        let (width, height, channels, maxval) = if magic == 7 {
+            let mut fields: [usize; 4] = [0; 4];
+            loop {
+                let key: &[u8] = tokens.token()?;
+                let field: usize = match key {
+                    b"ENDHDR" => break,
+                    b"WIDTH" => 0,
+                    b"HEIGHT" => 1,
+                    b"DEPTH" => 2,
+                    b"MAXVAL" => 3,
+                    _ => {
+                        // TUPLTYPE and anything unknown run to the end of the line
+                        while tokens.bytes.get(tokens.offset).is_some_and(|&b| b != b'\n') {
+                            tokens.offset += 1;
+                        }
+                        continue;
+                    }
+                };
+                fields[field] = tokens.number()?;
+            }
+            if !(1..=4).contains(&fields[2]) {
+                return Err(invalid("Unsupported PAM depth"));
+            }
+            (fields[0], fields[1], fields[2], fields[3])
+        } else {
+            let (width, height) = (tokens.number()?, tokens.number()?);
+            let channels: usize = if magic.is_multiple_of(3) { 3 } else { 1 };
+            let maxval: usize = if magic % 3 == 1 { 1 } else { tokens.number()? };
+            (width, height, channels, maxval)
+        };
+
+        if !(1..=65535).contains(&maxval) {
+            return Err(invalid("Netpbm maximum value out of range"));
+        }
+        let count: usize = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .ok_or_else(|| invalid("Netpbm image too large"))?;
+
+        // Every sample takes at least a byte, or a bit in a PBM raster
+        let remaining: usize = bytes.len().saturating_sub(tokens.offset);
+        let per_byte: usize = if magic == 4 { 8 } else { 1 };
+        if count > remaining.saturating_mul(per_byte) {
+            return Err(invalid("Not enough Netpbm image data"));
+        }
+
+        let samples: Vec<u16> = match magic {
+            1 => (0..count)
+                .map(|_| tokens.bit().map(|bit| 1 - bit))
+                .collect::<Result<_, _>>()?,
+            2 | 3 => (0..count)
+                .map(|_| tokens.number().map(|n| n.min(u16::MAX as usize) as u16))
+                .collect::<Result<_, _>>()?,
+            _ => {
+                // A single whitespace byte separates the header from binary data
+                let start: usize = tokens.offset + 1;
+                let raster: &[u8] = &bytes[start.min(bytes.len())..];
+
+                if magic == 4 {
+                    let stride: usize = width.div_ceil(8);
+                    if raster.len() < stride * height {
+                        return Err(invalid("Not enough Netpbm image data"));
+                    }
+                    (0..count)
+                        .map(|i| {
+                            let (x, y) = (i % width, i / width);
+                            1 - ((raster[y * stride + x / 8] >> (7 - x % 8)) & 1) as u16
+                        })
+                        .collect()
+                } else {
+                    let size: usize = if maxval > 255 { 2 } else { 1 };
+                    let raster: &[u8] = raster
+                        .get(..count * size)
+                        .ok_or_else(|| invalid("Not enough Netpbm image data"))?;
+                    match size {
+                        1 => raster.iter().map(|&b| b as u16).collect(),
+                        _ => raster
+                            .chunks_exact(2)
+                            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                            .collect(),
+                    }
+                }
+            }
+        };
+
+        if samples.iter().any(|&s| s as usize > maxval) {
+            return Err(invalid("Netpbm sample exceeds maximum value"));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            channels,
+            maxval: maxval as u16,
+            samples,
+        })
+    }
+}
+
+/// Alpha is included as a fourth channel if the image keeps it.
+impl From<&Image> for Netpbm {
+    fn from(image: &Image) -> Self {
+        let channels: usize = match image.channels {
+            Channels::Rgb => 3,
+            Channels::Rgba => 4,
+        };
+
+        Self {
+            width: image.width,
+            height: image.height,
+            channels,
+            maxval: 255,
+            samples: image
+                .data
+                .iter()
+                .flat_map(|p| [p.red, p.green, p.blue, p.alpha].into_iter().take(channels))
+                .map(u16::from)
+                .collect(),
+        }
+    }
+}
+
+/// Depths at the full 16-bit precision of the formats.
+impl From<&DepthBuffer> for Netpbm {
+    fn from(buffer: &DepthBuffer) -> Self {
+        Self {
+            width: buffer.width(),
+            height: buffer.height(),
+            channels: 1,
+            maxval: u16::MAX,
+            samples: buffer
+                .values()
+                .iter()
+                .map(|depth| (depth.clamp(0., 1.) * 65535.).round() as u16)
+                .collect(),
+        }
+    }
+}
+
+impl Image {
+    /// Decode any Netpbm image, scaling its samples to 8 bits and keeping alpha if there is any.
+    pub fn decode_netpbm(bytes: &[u8]) -> Result<Self, Error> {
+        let netpbm = Netpbm::decode(bytes)?;
+        let scale = |sample: u16| {
+            let maxval = netpbm.maxval as u32;
+            ((sample as u32 * 255 + maxval / 2) / maxval) as u8
+        };
+
+        let mut image = Image::blank(netpbm.width, netpbm.height);
+        if matches!(netpbm.channels, 2 | 4) {
+            image.channels = Channels::Rgba;
+        }
+
+        for (pixel, samples) in image.data.iter_mut().zip(netpbm.pixels()) {
+            let [red, green, blue] = netpbm.colour(samples).map(scale);
+            let alpha: u8 = match netpbm.channels {
+                2 | 4 => scale(samples[netpbm.channels - 1]),
+                _ => 255,
+            };
+            *pixel = Pixel {
+                red,
+                green,
+                blue,
+                alpha,
+            };
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Netpbm {
+        Netpbm {
+            width: 11,
+            height: 3,
+            channels: 3,
+            maxval: 1000,
+            samples: (0..99).map(|i| (i * 10) as u16).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        let image: Netpbm = gradient();
+        let grey: Vec<u16> = image.pixels().map(|p| image.grey(p)).collect();
+
+        for encoding in [Encoding::Ascii, Encoding::Binary] {
+            let ppm = Netpbm::decode(&image.encode(Format::Ppm(encoding))).unwrap();
+            assert_eq!(ppm, image);
+
+            let pgm = Netpbm::decode(&image.encode(Format::Pgm(encoding))).unwrap();
+            assert_eq!((pgm.channels, &pgm.samples), (1, &grey));
+
+            let pbm = Netpbm::decode(&image.encode(Format::Pbm(encoding))).unwrap();
+            let expected: Vec<u16> = grey.iter().map(|&g| (2 * g >= 1000) as u16).collect();
+            assert_eq!((pbm.maxval, &pbm.samples), (1, &expected));
+        }
+    }
+
+    #[test]
+    fn pam_keeps_alpha() {
+        let image = Netpbm {
+            width: 2,
+            height: 1,
+            channels: 4,
+            maxval: 255,
+            samples: vec![255, 0, 0, 128, 0, 0, 255, 0],
+        };
+
+        let pam: Vec<u8> = image.encode(Format::Pam);
+        assert!(
+            pam.starts_with(b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\n")
+        );
+        assert_eq!(Netpbm::decode(&pam).unwrap(), image);
+
+        let decoded = Image::decode_netpbm(&pam).unwrap();
+        assert_eq!(decoded.channels, Channels::Rgba);
+        assert_eq!(Netpbm::from(&decoded), image);
+    }
+
+    #[test]
+    fn decode_plain_with_comments() {
+        let pbm: &[u8] = b"P1\n# comment\n3 2\n010\n# another\n1 0 1\n";
+        assert_eq!(Netpbm::decode(pbm).unwrap().samples, [1, 0, 1, 0, 1, 0]);
+
+        let pgm: &[u8] = b"P2 2 1 # size\n65535\n0 65535\n";
+        let image = Image::decode_netpbm(pgm).unwrap();
+        assert_eq!(image.data[1], Pixel::from([255.; 3]));
+    }
+
+    #[test]
+    fn sixteen_bit_depth_export() {
+        let mut buffer = DepthBuffer::new(2, 1);
+        buffer.set_clear_value(0.5);
+
+        let pgm: Vec<u8> = Netpbm::from(&buffer).encode(Format::Pgm(Encoding::Binary));
+        assert_eq!(pgm, buffer.pgm());
+        assert_eq!(pgm[pgm.len() - 4..], [128, 0, 128, 0]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(Netpbm::decode(b"P8\n1 1\n").is_err());
+        assert!(Netpbm::decode(b"P5\n2 2\n255\n\x00").is_err());
+        assert!(Netpbm::decode(b"P2\n1 1\n10\n11\n").is_err());
+        assert!(Netpbm::decode(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 1\nENDHDR\n").is_err());
+
+        // Sizes whose sample count overflows, or far exceeds the data, fail before allocating
+        assert!(Netpbm::decode(b"P6\n4000000000 4000000000\n255\n").is_err());
+        assert!(Netpbm::decode(b"P3\n60000 60000\n255\n1 2 3\n").is_err());
+        assert!(Netpbm::decode(b"P4\n17 1\n\xff\xff").is_err());
+    }
+}
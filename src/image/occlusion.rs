@@ -0,0 +1,312 @@
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+use crate::image::pixel::Pixel;
+use crate::image::{Image, Position};
+use crate::math::vector::{Vec3d, add, cross_product, dot_product, scalar_mul, sub, unit};
+
+/// Screen-space ambient occlusion sampled in the hemisphere around each pixel's normal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ssao {
+    /// Samples taken per pixel.
+    pub samples: usize,
+    /// Radius of the hemisphere, in pixels.
+    pub radius: f64,
+    /// Height in pixels a surface must rise above a sample to occlude it, against self-occlusion
+    /// of flat surfaces.
+    pub bias: f64,
+    /// Radius in pixels of the bilateral blur smoothing the result, or 0 for none.
+    pub blur: usize,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            radius: 8.,
+            bias: 0.5,
+            blur: 2,
+        }
+    }
+}
+
+/// Side of the square tile of per-pixel rotations of the sample kernel.
+const NOISE_SIZE: usize = 4;
+
+/// Directions within the hemisphere around +z, spiralling out from the pole and lengthening so
+/// that samples gather near the centre, where occluders matter most.
+fn kernel(samples: usize) -> Vec<Vec3d> {
+    let golden_angle: f64 = PI * (3. - 5f64.sqrt());
+
+    (0..samples)
+        .map(|n| {
+            let t: f64 = (n as f64 + 0.5) / samples as f64;
+            let cos_theta: f64 = 1. - t;
+            let sin_theta: f64 = (1. - cos_theta * cos_theta).sqrt();
+            let (sin_phi, cos_phi) = (n as f64 * golden_angle).sin_cos();
+            let scale: f64 = 0.1 + 0.9 * t * t;
+            scalar_mul(
+                &[sin_theta * cos_phi, sin_theta * sin_phi, cos_theta],
+                scale,
+            )
+        })
+        .collect()
+}
+
+/// Rotate `direction` about the z axis by `angle`, then into the frame whose z axis is `normal`.
+fn oriented(direction: &Vec3d, normal: &Vec3d, angle: f64) -> Vec3d {
+    let (sin, cos) = angle.sin_cos();
+    let [x, y, z] = *direction;
+    let [x, y] = [x * cos - y * sin, x * sin + y * cos];
+
+    let axis: Vec3d = match normal[0].abs() < 0.9 {
+        true => [1., 0., 0.],
+        false => [0., 1., 0.],
+    };
+    let tangent: Vec3d = unit(&sub(&axis, &scalar_mul(normal, dot_product(normal, &axis))));
+    let bitangent: Vec3d = cross_product(normal, &tangent);
+    add(
+        &add(&scalar_mul(&tangent, x), &scalar_mul(&bitangent, y)),
+        &scalar_mul(normal, z),
+    )
+}
+
+impl Image {
+    /// Height of the surface towards the viewer at `index`, in pixels, taking the projected `z`
+    /// to span as many pixels as `x` does.
+    fn height_at(&self, index: usize) -> f64 {
+        let depth: f64 = self.zbuffer.get(index);
+        let z: f64 = match self.zbuffer.reversed() {
+            true => 2. * depth - 1.,
+            false => 1. - 2. * depth,
+        };
+        z * self.width as f64 / 2.
+    }
+
+    /// Column and row from the bottom of the pixel at `index`.
+    fn raster_position(&self, index: usize) -> [usize; 2] {
+        [index % self.width, self.height - index / self.width - 1]
+    }
+
+    /// Index of the pixel containing the raster point `x`, `y`, if it is within the image.
+    fn index_at(&self, x: f64, y: f64) -> Option<usize> {
+        if x < 0. || y < 0. {
+            return None;
+        }
+        self.raster_index(&Position {
+            x: x as usize,
+            y: y as usize,
+        })
+    }
+
+    /// Whether a triangle has been drawn to the pixel at `index`.
+    fn covered(&self, index: usize) -> bool {
+        self.zbuffer.get(index) != self.zbuffer.clear_value()
+    }
+
+    /// Fraction of the ambient light reaching each pixel, in the same order as the pixels, from
+    /// the depth buffer and the normals in the first three channels of the attachment called
+    /// `normals`. Points in the hemisphere around each normal occlude when the surface drawn at
+    /// them is nearer the viewer, and samples are rotated from pixel to pixel so that the blur
+    /// can smooth out their pattern. Pixels without a triangle are unoccluded. `None` if there is
+    /// no such attachment.
+    pub fn ssao(&self, normals: &str, settings: &Ssao) -> Option<Vec<f64>> {
+        let normals = self.attachment(normals)?;
+        let kernel: Vec<Vec3d> = kernel(settings.samples);
+
+        let occlusion: Vec<f64> = (0..self.width * self.height)
+            .map(|index| {
+                if !self.covered(index) || settings.samples == 0 {
+                    return 1.;
+                }
+
+                let [x, y] = self.raster_position(index);
+                let height: f64 = self.height_at(index);
+                let centre: Vec3d = [x as f64 + 0.5, y as f64 + 0.5, height];
+                let &[nx, ny, nz, ..] = normals.get(index) else {
+                    return 1.;
+                };
+                let normal: Vec3d = unit(&[nx, ny, nz].map(f64::from));
+                let noise: usize = x % NOISE_SIZE * NOISE_SIZE + y % NOISE_SIZE;
+                let angle: f64 = TAU * noise as f64 / (NOISE_SIZE * NOISE_SIZE) as f64;
+
+                let occluded: f64 = kernel
+                    .iter()
+                    .filter_map(|direction| {
+                        let offset: Vec3d = oriented(direction, &normal, angle);
+                        let [sx, sy, sz] = add(&centre, &scalar_mul(&offset, settings.radius));
+                        let surface: f64 = self.height_at(self.index_at(sx, sy)?);
+
+                        // Occluders much further away than the radius fade out
+                        let range: f64 = (settings.radius / (height - surface).abs()).min(1.);
+                        (surface >= sz + settings.bias).then_some(range)
+                    })
+                    .sum();
+                1. - occluded / settings.samples as f64
+            })
+            .collect();
+
+        Some(self.bilateral_blur(&occlusion, settings.blur, settings.radius))
+    }
+
+    /// Blur `values` over squares of `radius` pixels, weighting neighbours down as their height
+    /// differs from the pixel's, over a scale of `range` pixels, so that edges stay sharp.
+    fn bilateral_blur(&self, values: &[f64], radius: usize, range: f64) -> Vec<f64> {
+        let radius: i64 = radius as i64;
+
+        (0..values.len())
+            .map(|index| {
+                if radius == 0 || !self.covered(index) {
+                    return values[index];
+                }
+
+                let [x, y] = self.raster_position(index);
+                let height: f64 = self.height_at(index);
+                let (mut total, mut weights) = (0., 0.);
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (sx, sy) = (x as i64 + dx, y as i64 + dy);
+                        let Some(neighbour) = self.index_at(sx as f64, sy as f64) else {
+                            continue;
+                        };
+                        if !self.covered(neighbour) {
+                            continue;
+                        }
+
+                        let difference: f64 = (self.height_at(neighbour) - height) / range;
+                        let weight: f64 = (-difference * difference * 8.).exp();
+                        total += values[neighbour] * weight;
+                        weights += weight;
+                    }
+                }
+                total / weights
+            })
+            .collect()
+    }
+
+    /// Ambient occlusion from the depth buffer alone, as in the original lessons: along each of
+    /// `directions` directions across the screen, find the steepest angle to the horizon within
+    /// `radius` pixels, and average how much of the sky above it is open.
+    pub fn horizon_occlusion(&self, directions: usize, radius: f64) -> Vec<f64> {
+        (0..self.width * self.height)
+            .map(|index| {
+                if !self.covered(index) || directions == 0 {
+                    return 1.;
+                }
+
+                let [x, y] = self.raster_position(index);
+                let centre: [f64; 2] = [x as f64 + 0.5, y as f64 + 0.5];
+                let height: f64 = self.height_at(index);
+
+                let open: f64 = (0..directions)
+                    .map(|n| {
+                        let (sin, cos) = (TAU * n as f64 / directions as f64).sin_cos();
+                        let mut steepest: f64 = 0.;
+                        let mut distance: f64 = 1.;
+                        while distance <= radius {
+                            let [sx, sy] = [centre[0] + cos * distance, centre[1] + sin * distance];
+                            let Some(neighbour) = self.index_at(sx, sy) else {
+                                break;
+                            };
+                            let rise: f64 = self.height_at(neighbour) - height;
+                            steepest = steepest.max(rise.atan2(distance));
+                            distance += 1.;
+                        }
+                        FRAC_PI_2 - steepest
+                    })
+                    .sum();
+                open / (FRAC_PI_2 * directions as f64)
+            })
+            .collect()
+    }
+
+    /// Scale the colour of every pixel by its value in `occlusion`, as `ssao` and
+    /// `horizon_occlusion` return them.
+    pub fn apply_occlusion(&mut self, occlusion: &[f64]) {
+        for (pixel, &visible) in self.data.iter_mut().zip(occlusion) {
+            let [red, green, blue] =
+                [pixel.red, pixel.green, pixel.blue].map(|c| (c as f64 * visible).round() as u8);
+            *pixel = Pixel {
+                red,
+                green,
+                blue,
+                ..*pixel
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vertex;
+    use crate::image::pixel::WHITE;
+
+    const SIZE: usize = 32;
+
+    /// Floor facing the viewer, with a raised square in its middle, and normals attached.
+    fn scene() -> Image {
+        let mut image = Image::blank(SIZE, SIZE);
+        image.add_attachment("normal", 3);
+
+        let square = |half: f64, z: f64| {
+            [[-half, -half], [half, -half], [half, half], [-half, half]]
+                .map(|[x, y]| Vertex::from([x, y, z]))
+        };
+        for [a, b, c, d] in [square(1., -0.5), square(0.25, 0.)] {
+            for [i, j, k] in [[a, b, c], [a, c, d]] {
+                image.gbuffer_triangle(&i, &j, &k, |_, outputs| {
+                    outputs.set("normal", &[0., 0., 1.]);
+                    WHITE
+                });
+            }
+        }
+        image
+    }
+
+    /// Occlusion at a pixel `x` and `y` from the bottom left.
+    fn at(occlusion: &[f64], x: usize, y: usize) -> f64 {
+        occlusion[x + SIZE * (SIZE - y - 1)]
+    }
+
+    #[test]
+    fn open_surfaces_are_unoccluded_and_creases_darkened() {
+        let image: Image = scene();
+        let unblurred = Ssao {
+            blur: 0,
+            ..Default::default()
+        };
+
+        for occlusion in [
+            image.ssao("normal", &unblurred).unwrap(),
+            image.horizon_occlusion(8, 8.),
+        ] {
+            // Far out on the floor, on top of the square, and on the floor against its side
+            assert!(at(&occlusion, 1, 1) > 0.99);
+            assert!(at(&occlusion, 16, 16) > 0.99);
+            assert!(at(&occlusion, 11, 16) < 0.9);
+            assert!(occlusion.iter().all(|&o| (0. ..=1.).contains(&o)));
+        }
+        assert!(image.ssao("missing", &unblurred).is_none());
+    }
+
+    #[test]
+    fn blur_keeps_edges() {
+        let image: Image = scene();
+        let occlusion: Vec<f64> = image.ssao("normal", &Ssao::default()).unwrap();
+
+        // The top of the square's edge is not darkened by the floor beside it
+        assert!(at(&occlusion, 12, 16) > 0.99);
+        assert!(at(&occlusion, 11, 16) < 0.9);
+    }
+
+    #[test]
+    fn occlusion_darkens_colour() {
+        let mut image: Image = scene();
+        let occlusion: Vec<f64> = image.horizon_occlusion(8, 8.);
+        image.apply_occlusion(&occlusion);
+
+        let darkened = image.get(&Position { x: 11, y: 16 }).unwrap();
+        assert!(darkened.red < 230 && darkened.alpha == 255);
+        assert_eq!(image.get(&Position { x: 16, y: 16 }), Some(WHITE));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::image::{Image, Position};
+use crate::math::vector::Vec3d;
+
+/// Identifies a triangle of a mesh.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrimitiveId {
+    pub mesh: usize,
+    /// Index of the face in the mesh's geometry.
+    pub triangle: usize,
+}
+
+/// Triangle that won the depth test at a pixel, and where on it the pixel's centre lies.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub id: PrimitiveId,
+    /// Barycentric weights of the triangle's vertices, in screen space, or perspective-correct
+    /// and over the unclipped vertices for triangles drawn by `perspective_triangle`.
+    pub barycentric: Vec3d,
+}
+
+impl Image {
+    /// Start recording which triangle is nearest at every pixel, for `pick`.
+    pub fn enable_picking(&mut self) {
+        self.hits = Some(vec![None; self.width * self.height]);
+    }
+
+    /// Set the id recorded for triangles rasterised from now on, or `None` for triangles that
+    /// cannot be picked.
+    pub fn set_primitive(&mut self, id: Option<PrimitiveId>) {
+        self.primitive = id;
+    }
+
+    /// Id recorded for triangles rasterised from now on.
+    pub fn primitive(&self) -> Option<PrimitiveId> {
+        self.primitive
+    }
+
+    /// Triangle drawn nearest at the pixel at `x` and `y`, if picking is enabled and a triangle
+    /// with an id covers it. When multisampling, this is the last triangle to cover any sample.
+    pub fn pick(&self, x: usize, y: usize) -> Option<Hit> {
+        let index: usize = self.index(&Position { x, y })?;
+        self.hits.as_ref()?[index]
+    }
+
+    /// Record the current primitive at `index` after it passed the depth test there, or that no
+    /// identified triangle is nearest if there is none.
+    pub(super) fn record_hit(&mut self, index: usize, barycentric: Vec3d) {
+        if let Some(hits) = &mut self.hits {
+            hits[index] = self.primitive.map(|id| Hit { id, barycentric });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vertex;
+    use crate::image::pixel::WHITE;
+
+    #[test]
+    fn nearest_triangle_is_picked() {
+        let [a, b, c, d] = [[-1., -1.], [1., -1.], [1., 1.], [-1., 1.]];
+        let at = |[x, y]: [f64; 2], z: f64| Vertex::from([x, y, z]);
+
+        let mut image = Image::blank(8, 8);
+        image.enable_picking();
+        image.set_primitive(Some(PrimitiveId {
+            mesh: 1,
+            triangle: 4,
+        }));
+        image.shaded_triangle(&at(a, 0.), &at(b, 0.), &at(c, 0.), |_| WHITE);
+        // Behind the first triangle where they overlap, and in front of nothing
+        image.set_primitive(Some(PrimitiveId {
+            mesh: 2,
+            triangle: 0,
+        }));
+        image.shaded_triangle(&at(a, -0.5), &at(c, -0.5), &at(d, -0.5), |_| WHITE);
+        image.set_primitive(None);
+        image.shaded_triangle(&at(a, 0.5), &at(b, 0.5), &at(d, 0.5), |_| WHITE);
+
+        let first: Hit = image.pick(6, 3).unwrap();
+        assert_eq!(
+            first.id,
+            PrimitiveId {
+                mesh: 1,
+                triangle: 4,
+            }
+        );
+        // The pixel centre at (6.5, 3.5) of 8
+        let expected: Vec3d = [0.1875, 0.375, 0.4375];
+        assert!((0..3).all(|n| (first.barycentric[n] - expected[n]).abs() < 1e-9));
+
+        assert_eq!(image.pick(2, 6).unwrap().id.mesh, 2);
+        // Nearest is the triangle without an id
+        assert_eq!(image.pick(2, 1), None);
+        assert_eq!(image.pick(8, 0), None);
+        assert_eq!(Image::blank(8, 8).pick(0, 0), None);
+    }
+}
@@ -1,36 +1,108 @@
-#[derive(Copy, Clone)]
+use crate::math::vector::{Vec3d, Vec4d};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Pixel {
     pub(crate) red: u8,
     pub(crate) green: u8,
     pub(crate) blue: u8,
+    /// Coverage, with 0 fully transparent. Colour channels are not premultiplied by it.
+    pub(crate) alpha: u8,
 }
 
 pub const RED: Pixel = Pixel {
     red: 255,
     green: 0,
     blue: 0,
+    alpha: 255,
 };
 
 pub const GREEN: Pixel = Pixel {
     red: 0,
     green: 255,
     blue: 0,
+    alpha: 255,
 };
 
 pub const BLUE: Pixel = Pixel {
     red: 0,
     green: 0,
     blue: 255,
+    alpha: 255,
 };
 
 pub const YELLOW: Pixel = Pixel {
     red: 255,
     green: 255,
     blue: 0,
+    alpha: 255,
 };
 
 pub const WHITE: Pixel = Pixel {
     red: 255,
     green: 255,
     blue: 255,
+    alpha: 255,
+};
+
+pub const TRANSPARENT: Pixel = Pixel {
+    red: 0,
+    green: 0,
+    blue: 0,
+    alpha: 0,
 };
+
+impl From<Pixel> for Vec3d {
+    fn from(pixel: Pixel) -> Self {
+        [pixel.red as f64, pixel.green as f64, pixel.blue as f64]
+    }
+}
+
+impl From<Vec3d> for Pixel {
+    fn from([red, green, blue]: Vec3d) -> Self {
+        let channel = |value: f64| value.round().clamp(0., 255.) as u8;
+
+        Pixel {
+            red: channel(red),
+            green: channel(green),
+            blue: channel(blue),
+            alpha: 255,
+        }
+    }
+}
+
+impl From<Pixel> for Vec4d {
+    fn from(pixel: Pixel) -> Self {
+        [pixel.red, pixel.green, pixel.blue, pixel.alpha].map(f64::from)
+    }
+}
+
+impl From<Vec4d> for Pixel {
+    fn from(rgba: Vec4d) -> Self {
+        let [red, green, blue, alpha] = rgba.map(|value| value.round().clamp(0., 255.) as u8);
+
+        Pixel {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+}
+
+/// Decode an sRGB-encoded channel in `[0, 1]` into linear light.
+pub fn srgb_to_linear(channel: f64) -> f64 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear channel in `[0, 1]` with the sRGB transfer function.
+pub fn linear_to_srgb(channel: f64) -> f64 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1. / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,555 @@
+pub mod zlib;
+
+use std::io::{Error, ErrorKind};
+
+use crate::image::pixel::Pixel;
+use crate::image::{Channels, Image};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Largest IDAT chunk written, so that readers never need to buffer a whole image in one chunk.
+const MAX_CHUNK: usize = 1 << 16;
+/// Longest side the PNG specification allows.
+const MAX_SIZE: usize = (1 << 31) - 1;
+/// Starting column and row, then column and row spacing, of the pixels of an interlace pass.
+type Pass = (usize, usize, usize, usize);
+/// The seven Adam7 passes.
+const ADAM7: [Pass; 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start: usize = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc: u32 = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p: i16 = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Value the filter type predicts for byte `i` of a row, from the bytes to its left, above, and
+/// above left, `bpp` bytes being one pixel.
+fn predict(filter: u8, row: &[u8], previous: &[u8], i: usize, bpp: usize) -> u8 {
+    let left: u8 = if i >= bpp { row[i - bpp] } else { 0 };
+    let up: u8 = previous[i];
+    let up_left: u8 = if i >= bpp { previous[i - bpp] } else { 0 };
+
+    match filter {
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        4 => paeth(left, up, up_left),
+        _ => 0,
+    }
+}
+
+/// Filter a row with each filter type, keeping the one whose output has the smallest sum of
+/// absolute values, which tends to compress best.
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    (0..5)
+        .map(|filter: u8| {
+            let mut filtered: Vec<u8> = vec![filter];
+            filtered.extend(
+                (0..row.len()).map(|i| row[i].wrapping_sub(predict(filter, row, previous, i, bpp))),
+            );
+            filtered
+        })
+        .min_by_key(|filtered| {
+            filtered[1..]
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as usize)
+                .sum::<usize>()
+        })
+        .unwrap()
+}
+
+fn unfilter_row(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), Error> {
+    if filter > 4 {
+        return Err(invalid("Invalid filter type"));
+    }
+
+    for i in 0..row.len() {
+        row[i] = row[i].wrapping_add(predict(filter, row, previous, i, bpp));
+    }
+    Ok(())
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    colour_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != 13 {
+            return Err(invalid("Invalid IHDR length"));
+        }
+
+        let header = Self {
+            width: u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize,
+            height: u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize,
+            bit_depth: data[8],
+            colour_type: data[9],
+            interlaced: data[12] == 1,
+        };
+
+        let depths: &[u8] = match header.colour_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(invalid("Invalid colour type")),
+        };
+        if !depths.contains(&header.bit_depth) {
+            return Err(invalid("Invalid bit depth for colour type"));
+        }
+        if [header.width, header.height].contains(&0) || header.width.max(header.height) > MAX_SIZE
+        {
+            return Err(invalid("PNG images must be 1 to 2^31 - 1 pixels on a side"));
+        }
+        if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+            return Err(invalid(
+                "Unsupported compression, filter or interlace method",
+            ));
+        }
+
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.colour_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in a row of `width` pixels, excluding the filter type.
+    fn stride(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Each pass that has pixels, with its width and height in pixels.
+    fn passes(&self) -> impl Iterator<Item = (Pass, usize, usize)> + '_ {
+        let passes: &[Pass] = match self.interlaced {
+            true => &ADAM7,
+            false => &[(0, 0, 1, 1)],
+        };
+        passes.iter().filter_map(|&(x0, y0, dx, dy)| {
+            let width: usize = self.width.saturating_sub(x0).div_ceil(dx);
+            let height: usize = self.height.saturating_sub(y0).div_ceil(dy);
+            (width > 0 && height > 0).then_some(((x0, y0, dx, dy), width, height))
+        })
+    }
+
+    /// Bytes of scanlines in the image, filter types included, or `None` if that overflows.
+    fn data_size(&self) -> Option<usize> {
+        self.passes().try_fold(0usize, |total, (_, width, height)| {
+            let line: usize = width
+                .checked_mul(self.bits_per_pixel())?
+                .div_ceil(8)
+                .checked_add(1)?;
+            total.checked_add(line.checked_mul(height)?)
+        })
+    }
+
+    /// Sample `index` of a row, before any scaling to 8 bits.
+    fn sample(&self, row: &[u8], index: usize) -> u16 {
+        match self.bit_depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+            8 => row[index] as u16,
+            depth => {
+                let bit: usize = index * depth as usize;
+                let shift: usize = 8 - depth as usize - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+            }
+        }
+    }
+
+    /// Scale a sample to 8 bits.
+    fn scale(&self, sample: u16) -> u8 {
+        match self.bit_depth {
+            16 => (sample >> 8) as u8,
+            depth => (sample as u32 * 255 / ((1 << depth) - 1)) as u8,
+        }
+    }
+
+    /// Pixel `x` of a row. `transparent` holds the raw samples of a colour key, if the image has
+    /// one, and palettes carry their own alpha.
+    fn pixel(
+        &self,
+        row: &[u8],
+        x: usize,
+        palette: &[Pixel],
+        transparent: Option<&[u16]>,
+    ) -> Result<Pixel, Error> {
+        let channels: usize = self.channels();
+        let raw: Vec<u16> = (0..channels)
+            .map(|c| self.sample(row, x * channels + c))
+            .collect();
+        let channel = |c: usize| self.scale(raw[c]);
+
+        if self.colour_type == 3 {
+            return palette
+                .get(raw[0] as usize)
+                .copied()
+                .ok_or_else(|| invalid("Palette index out of range"));
+        }
+
+        let alpha: u8 = match self.colour_type {
+            4 => channel(1),
+            6 => channel(3),
+            _ if transparent == Some(&raw[..]) => 0,
+            _ => 255,
+        };
+        let (red, green, blue) = match self.colour_type {
+            2 | 6 => (channel(0), channel(1), channel(2)),
+            _ => (channel(0), channel(0), channel(0)),
+        };
+
+        Ok(Pixel {
+            red,
+            green,
+            blue,
+            alpha,
+        })
+    }
+}
+
+impl Image {
+    /// 8-bit truecolour PNG, with alpha if the image keeps it, and each row filtered using
+    /// whichever filter type suits it best.
+    pub fn png(&self) -> Vec<u8> {
+        let (colour_type, bpp): (u8, usize) = match self.channels {
+            Channels::Rgb => (2, 3),
+            Channels::Rgba => (6, 4),
+        };
+        let stride: usize = bpp * self.width;
+        let mut scanlines: Vec<u8> = Vec::with_capacity((stride + 1) * self.height);
+        let mut previous: Vec<u8> = vec![0; stride];
+
+        for row in self.data.chunks(self.width.max(1)) {
+            let row: Vec<u8> = row
+                .iter()
+                .flat_map(|p| [p.red, p.green, p.blue, p.alpha].into_iter().take(bpp))
+                .collect();
+            scanlines.extend(filter_row(&row, &previous, bpp));
+            previous = row;
+        }
+
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, colour_type, 0, 0, 0]);
+
+        let mut png: Vec<u8> = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        for data in zlib::compress(&scanlines).chunks(MAX_CHUNK) {
+            write_chunk(&mut png, b"IDAT", data);
+        }
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    /// Decode a PNG of any standard colour type, bit depth and interlacing. Images with an alpha
+    /// channel or transparency chunk keep alpha.
+    pub fn decode_png(bytes: &[u8]) -> Result<Self, Error> {
+        if !bytes.starts_with(&SIGNATURE) {
+            return Err(invalid("Missing PNG signature"));
+        }
+
+        let mut header: Option<Header> = None;
+        let mut palette: Vec<Pixel> = Vec::new();
+        let mut transparency: Option<Vec<u8>> = None;
+        let mut compressed: Vec<u8> = Vec::new();
+        let mut offset: usize = SIGNATURE.len();
+
+        loop {
+            let length: usize = bytes
+                .get(offset..offset + 4)
+                .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+                .ok_or_else(|| invalid("Missing IEND chunk"))?;
+            let chunk: &[u8] = bytes
+                .get(offset + 4..offset + 8 + length)
+                .ok_or_else(|| invalid("Truncated chunk"))?;
+            let crc: u32 = bytes
+                .get(offset + 8 + length..offset + 12 + length)
+                .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+                .ok_or_else(|| invalid("Truncated chunk"))?;
+            if crc != crc32(chunk) {
+                return Err(invalid("Chunk CRC mismatch"));
+            }
+            offset += 12 + length;
+
+            let (kind, data) = chunk.split_at(4);
+            match kind {
+                b"IHDR" => header = Some(Header::decode(data)?),
+                b"PLTE" => {
+                    palette = data
+                        .chunks_exact(3)
+                        .map(|rgb| Pixel {
+                            red: rgb[0],
+                            green: rgb[1],
+                            blue: rgb[2],
+                            alpha: 255,
+                        })
+                        .collect()
+                }
+                b"tRNS" => transparency = Some(data.to_vec()),
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                _ if kind[0].is_ascii_uppercase() => {
+                    return Err(invalid("Unknown critical chunk"));
+                }
+                _ => {}
+            }
+        }
+
+        let header: Header = header.ok_or_else(|| invalid("Missing IHDR chunk"))?;
+        let size: usize = header
+            .data_size()
+            .ok_or_else(|| invalid("PNG image too large"))?;
+        // The header cannot claim more pixels than the data holds into a huge allocation
+        let scanlines: Vec<u8> = zlib::decompress(&compressed, size)?;
+        if scanlines.len() != size {
+            return Err(invalid("Not enough image data"));
+        }
+
+        // Palettes take alpha per entry, and other colour types a single fully transparent colour
+        let mut transparent: Option<Vec<u16>> = None;
+        if let Some(alphas) = &transparency {
+            match header.colour_type {
+                3 => std::iter::zip(&mut palette, alphas).for_each(|(p, &a)| p.alpha = a),
+                _ => {
+                    let samples = alphas
+                        .chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]));
+                    transparent = Some(samples.collect());
+                }
+            }
+        }
+
+        let bpp: usize = header.bits_per_pixel().div_ceil(8);
+        let mut image = Image::blank(header.width, header.height);
+        if transparency.is_some() || header.colour_type == 4 || header.colour_type == 6 {
+            image.channels = Channels::Rgba;
+        }
+        let mut offset: usize = 0;
+
+        for ((x0, y0, dx, dy), width, height) in header.passes() {
+            let stride: usize = header.stride(width);
+            let mut previous: Vec<u8> = vec![0; stride];
+            for y in 0..height {
+                let line: &[u8] = &scanlines[offset..offset + stride + 1];
+                offset += stride + 1;
+
+                let mut row: Vec<u8> = line[1..].to_vec();
+                unfilter_row(line[0], &mut row, &previous, bpp)?;
+
+                for x in 0..width {
+                    let index: usize = x0 + x * dx + header.width * (y0 + y * dy);
+                    image.data[index] = header.pixel(&row, x, &palette, transparent.as_deref())?;
+                }
+                previous = row;
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::pixel;
+
+    /// PNG with the given header fields and unfiltered scanlines.
+    fn encode(
+        width: u32,
+        height: u32,
+        depth_and_type: [u8; 2],
+        interlace: u8,
+        rows: &[u8],
+    ) -> Vec<u8> {
+        let mut header: Vec<u8> = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        header.extend_from_slice(&[depth_and_type[0], depth_and_type[1], 0, 0, interlace]);
+
+        let mut png: Vec<u8> = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        if depth_and_type[1] == 3 {
+            write_chunk(&mut png, b"PLTE", &[255, 0, 0, 0, 0, 255]);
+        }
+        write_chunk(&mut png, b"IDAT", &zlib::compress(rows));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn grey(value: u8) -> Pixel {
+        Pixel {
+            red: value,
+            green: value,
+            blue: value,
+            alpha: 255,
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (width, height) = (37, 23);
+
+        for channels in [Channels::Rgb, Channels::Rgba] {
+            let mut image = Image::blank(width, height);
+            image.set_channels(channels);
+            for (index, pixel) in image.data.iter_mut().enumerate() {
+                let (x, y) = (index % width, index / width);
+                *pixel = Pixel {
+                    red: (x * 7) as u8,
+                    green: (y * 11) as u8,
+                    blue: ((x * y) % 256) as u8,
+                    alpha: match channels {
+                        Channels::Rgb => 255,
+                        Channels::Rgba => ((x + y) * 5) as u8,
+                    },
+                };
+            }
+
+            let decoded = Image::decode_png(&image.png()).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, height));
+            assert_eq!(decoded.channels, channels);
+            assert_eq!(decoded.data, image.data);
+        }
+    }
+
+    #[test]
+    fn decode_low_bit_depths() {
+        // Two rows of four 2-bit grey samples, and a 1-bit palette image of the same size
+        let greyscale = encode(4, 2, [2, 0], 0, &[0, 0b00011011, 0, 0b11100100]);
+        let image = Image::decode_png(&greyscale).unwrap();
+        assert_eq!(image.data[..4], [grey(0), grey(85), grey(170), grey(255)]);
+        assert_eq!(image.data[4], grey(255));
+
+        let palette = encode(4, 2, [1, 3], 0, &[0, 0b10100000, 0, 0b01010000]);
+        let image = Image::decode_png(&palette).unwrap();
+        assert_eq!(image.data[0], pixel::BLUE);
+        assert_eq!(image.data[1], pixel::RED);
+        assert_eq!(image.data[4], pixel::RED);
+    }
+
+    #[test]
+    fn decode_interlaced_16_bit_with_alpha() {
+        // 3x3 image whose grey level is the index of its pixel, so pass order does not matter
+        let sample = |index: u8| [index * 20, 0, 255, 255];
+
+        // Rows of the passes that are not empty: (0, 0), then (2, 0), then (0, 2) and (2, 2),
+        // then (1, 0) and (1, 2) on separate rows, then the middle row
+        let passes: [&[&[u8]]; 5] = [&[&[0]], &[&[2]], &[&[6, 8]], &[&[1], &[7]], &[&[3, 4, 5]]];
+        let mut rows: Vec<u8> = Vec::new();
+        for line in passes.iter().flat_map(|pass| pass.iter()) {
+            rows.push(0);
+            line.iter().for_each(|&index| rows.extend(sample(index)));
+        }
+
+        let image = Image::decode_png(&encode(3, 3, [16, 4], 1, &rows)).unwrap();
+        for (index, pixel) in image.data.iter().enumerate() {
+            assert_eq!(*pixel, grey(index as u8 * 20));
+        }
+        assert_eq!(image.channels, Channels::Rgba);
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let mut png: Vec<u8> = Image::blank(2, 2).png();
+        assert!(Image::decode_png(&png[..png.len() - 12]).is_err());
+
+        png[20] ^= 1;
+        assert!(Image::decode_png(&png).is_err());
+        assert!(Image::decode_png(b"not a png").is_err());
+
+        // Scanlines a byte short, or a byte long
+        assert!(Image::decode_png(&encode(2, 1, [8, 0], 0, &[0, 1])).is_err());
+        assert!(Image::decode_png(&encode(2, 1, [8, 0], 0, &[0, 1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        for [width, height] in [[0, 1], [1, 0], [1 << 31, 1]] {
+            assert!(Image::decode_png(&encode(width, height, [8, 0], 0, &[0, 0])).is_err());
+        }
+
+        // Far more pixels than the data holds, and more rows than fit in memory, are rejected
+        // without allocating the image
+        let max: u32 = (1 << 31) - 1;
+        for [width, height] in [[65535, 65535], [max, max]] {
+            assert!(Image::decode_png(&encode(width, height, [16, 6], 1, &[0; 9])).is_err());
+        }
+
+        // A small file inflating to far more than its one pixel
+        assert!(Image::decode_png(&encode(1, 1, [8, 0], 0, &vec![0; 1 << 20])).is_err());
+    }
+}
@@ -0,0 +1,885 @@
+use std::collections::BinaryHeap;
+use std::io::{Error, ErrorKind};
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Number of earlier occurrences of a hash tried when looking for the longest match.
+const MAX_CHAIN: usize = 128;
+/// Number of symbols grouped into a block, each block getting its own Huffman codes.
+const BLOCK_SYMBOLS: usize = 1 << 16;
+const MAX_STORED: usize = 65535;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the lengths of the code length code are stored in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b): (u32, u32) = (1, 0);
+
+    // Largest run of bytes that cannot overflow `b` before taking the modulus
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literals = vec![8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+
+    (literals, vec![5; 30])
+}
+
+/// Canonical Huffman codes for the given code lengths, as in RFC 1951 section 3.2.2.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; 16];
+    lengths
+        .iter()
+        .filter(|&&l| l > 0)
+        .for_each(|&l| counts[l as usize] += 1);
+
+    let mut next = [0u16; 16];
+    let mut code: u16 = 0;
+    for bits in 1..16 {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let code = next[l as usize];
+            next[l as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Huffman code lengths for `frequencies`, no longer than `limit` bits. There are always at
+/// least two codes, since some decoders reject a code with a single symbol.
+fn code_lengths(frequencies: &[u32], limit: usize) -> Vec<u8> {
+    let mut used: Vec<usize> = (0..frequencies.len())
+        .filter(|&s| frequencies[s] > 0)
+        .collect();
+    for filler in 0..2 {
+        if used.len() < 2 && !used.contains(&filler) {
+            used.push(filler);
+        }
+    }
+
+    // Build the tree bottom-up, tracking each node's parent to find the depth of the leaves
+    let mut parents: Vec<usize> = vec![usize::MAX; used.len()];
+    let mut heap: BinaryHeap<std::cmp::Reverse<(u64, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &s)| std::cmp::Reverse((frequencies[s].max(1) as u64, node)))
+        .collect();
+    while heap.len() > 1 {
+        let std::cmp::Reverse((left_weight, left)) = heap.pop().unwrap();
+        let std::cmp::Reverse((right_weight, right)) = heap.pop().unwrap();
+
+        let parent: usize = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = parent;
+        parents[right] = parent;
+        heap.push(std::cmp::Reverse((left_weight + right_weight, parent)));
+    }
+
+    let depth = |mut node: usize| {
+        let mut depth: usize = 0;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        depth
+    };
+
+    let mut counts: Vec<usize> = vec![0; used.len() + 1];
+    (0..used.len()).for_each(|leaf| counts[depth(leaf)] += 1);
+
+    // Move leaves deeper than the limit up the tree, keeping the code complete
+    for bits in (limit + 1..counts.len()).rev() {
+        while counts[bits] > 0 {
+            let mut shallower: usize = bits - 2;
+            while counts[shallower] == 0 {
+                shallower -= 1;
+            }
+            counts[bits] -= 2;
+            counts[bits - 1] += 1;
+            counts[shallower + 1] += 2;
+            counts[shallower] -= 1;
+        }
+    }
+
+    // The most frequent symbols take the shortest codes
+    used.sort_by_key(|&s| (std::cmp::Reverse(frequencies[s]), s));
+    let mut lengths: Vec<u8> = vec![0; frequencies.len()];
+    let mut symbols = used.into_iter();
+    for (bits, &count) in counts.iter().enumerate().take(limit + 1) {
+        for symbol in symbols.by_ref().take(count) {
+            lengths[symbol] = bits as u8;
+        }
+    }
+
+    lengths
+}
+
+enum Symbol {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+impl Symbol {
+    fn length_code(length: usize) -> usize {
+        LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap()
+    }
+
+    fn distance_code(distance: usize) -> usize {
+        DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap()
+    }
+}
+
+/// Greedy LZ77 parse using hash chains over the previous `WINDOW_SIZE` bytes.
+fn lz77(data: &[u8]) -> Vec<Symbol> {
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff
+    };
+    let mut head: Vec<usize> = vec![usize::MAX; 1 << 15];
+    let mut previous: Vec<usize> = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut Vec<usize>, previous: &mut Vec<usize>| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            previous[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut symbols = Vec::new();
+    let mut i: usize = 0;
+    while i < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let longest: usize = (data.len() - i).min(MAX_MATCH);
+            let mut candidate: usize = head[hash(i)];
+            let mut chain: usize = 0;
+
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length: usize = (0..longest)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, i - candidate);
+                    if length == longest {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            symbols.push(Symbol::Match {
+                length: best_length,
+                distance: best_distance,
+            });
+            (i..i + best_length).for_each(|j| insert(j, &mut head, &mut previous));
+            i += best_length;
+        } else {
+            symbols.push(Symbol::Literal(data[i]));
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+
+    symbols
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Write the low `bits` of `value`, least significant bit first.
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is packed starting from its most significant bit.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed: u32 = (code.reverse_bits() >> (16 - length as u32)) as u32;
+        self.write(reversed, length as u32);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+/// Code length symbols, with runs folded into the repeat codes 16, 17 and 18 and their extra bits.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i: usize = 0;
+
+    while i < lengths.len() {
+        let length: u8 = lengths[i];
+        let run: usize = lengths[i..].iter().take_while(|&&l| l == length).count();
+
+        if length == 0 && run >= 11 {
+            let run = run.min(138);
+            encoded.push((18, (run - 11) as u8));
+            i += run;
+        } else if length == 0 && run >= 3 {
+            let run = run.min(10);
+            encoded.push((17, (run - 3) as u8));
+            i += run;
+        } else {
+            encoded.push((length, 0));
+            i += 1;
+
+            let mut remaining: usize = run - 1;
+            while length != 0 && remaining >= 3 {
+                let repeat = remaining.min(6);
+                encoded.push((16, (repeat - 3) as u8));
+                i += repeat;
+                remaining -= repeat;
+            }
+        }
+    }
+
+    encoded
+}
+
+struct Block<'a> {
+    symbols: &'a [Symbol],
+    bytes: &'a [u8],
+}
+
+impl Block<'_> {
+    fn frequencies(&self) -> (Vec<u32>, Vec<u32>) {
+        let mut literals = vec![0u32; 286];
+        let mut distances = vec![0u32; 30];
+
+        for symbol in self.symbols {
+            match *symbol {
+                Symbol::Literal(byte) => literals[byte as usize] += 1,
+                Symbol::Match { length, distance } => {
+                    literals[257 + Symbol::length_code(length)] += 1;
+                    distances[Symbol::distance_code(distance)] += 1;
+                }
+            }
+        }
+        literals[END_OF_BLOCK] += 1;
+
+        (literals, distances)
+    }
+
+    /// Size in bits of the block's symbols under the given code lengths.
+    fn cost(&self, literals: &[u8], distances: &[u8]) -> usize {
+        let (literal_counts, distance_counts) = self.frequencies();
+        let extra: usize = self
+            .symbols
+            .iter()
+            .map(|symbol| match *symbol {
+                Symbol::Literal(_) => 0,
+                Symbol::Match { length, distance } => {
+                    LENGTH_EXTRA[Symbol::length_code(length)] as usize
+                        + DISTANCE_EXTRA[Symbol::distance_code(distance)] as usize
+                }
+            })
+            .sum();
+
+        let weighted = |counts: &[u32], lengths: &[u8]| -> usize {
+            std::iter::zip(counts, lengths)
+                .map(|(&c, &l)| c as usize * l as usize)
+                .sum()
+        };
+        weighted(&literal_counts, literals) + weighted(&distance_counts, distances) + extra
+    }
+
+    fn write_symbols(&self, writer: &mut BitWriter, literals: &[u8], distances: &[u8]) {
+        let literal_codes: Vec<u16> = canonical_codes(literals);
+        let distance_codes: Vec<u16> = canonical_codes(distances);
+
+        for symbol in self.symbols {
+            match *symbol {
+                Symbol::Literal(byte) => {
+                    writer.write_code(literal_codes[byte as usize], literals[byte as usize])
+                }
+                Symbol::Match { length, distance } => {
+                    let code: usize = Symbol::length_code(length);
+                    writer.write_code(literal_codes[257 + code], literals[257 + code]);
+                    writer.write(
+                        (length - LENGTH_BASE[code] as usize) as u32,
+                        LENGTH_EXTRA[code] as u32,
+                    );
+
+                    let code: usize = Symbol::distance_code(distance);
+                    writer.write_code(distance_codes[code], distances[code]);
+                    writer.write(
+                        (distance - DISTANCE_BASE[code] as usize) as u32,
+                        DISTANCE_EXTRA[code] as u32,
+                    );
+                }
+            }
+        }
+        writer.write_code(literal_codes[END_OF_BLOCK], literals[END_OF_BLOCK]);
+    }
+
+    fn write_stored(&self, writer: &mut BitWriter, last: bool) {
+        let chunks: Vec<&[u8]> = match self.bytes.is_empty() {
+            true => vec![&[]],
+            false => self.bytes.chunks(MAX_STORED).collect(),
+        };
+
+        for (n, chunk) in chunks.iter().enumerate() {
+            writer.write((last && n + 1 == chunks.len()) as u32, 1);
+            writer.write(0, 2);
+            writer.align();
+
+            let length = chunk.len() as u16;
+            writer.bytes.extend_from_slice(&length.to_le_bytes());
+            writer.bytes.extend_from_slice(&(!length).to_le_bytes());
+            writer.bytes.extend_from_slice(chunk);
+        }
+    }
+
+    fn write_fixed(&self, writer: &mut BitWriter, last: bool) {
+        let (literals, distances) = fixed_lengths();
+        writer.write(last as u32, 1);
+        writer.write(1, 2);
+        self.write_symbols(writer, &literals, &distances);
+    }
+
+    /// Code lengths of a dynamic block, and the header describing them.
+    fn dynamic_header(&self) -> (Vec<u8>, Vec<u8>, BitWriter) {
+        let (literal_counts, distance_counts) = self.frequencies();
+        let literals: Vec<u8> = code_lengths(&literal_counts, 15);
+        let distances: Vec<u8> = code_lengths(&distance_counts, 15);
+
+        let literal_count: usize = 257.max(literals.iter().rposition(|&l| l > 0).unwrap() + 1);
+        let distance_count: usize = 1.max(distances.iter().rposition(|&l| l > 0).unwrap() + 1);
+
+        let all_lengths: Vec<u8> =
+            [&literals[..literal_count], &distances[..distance_count]].concat();
+        let encoded: Vec<(u8, u8)> = run_length_encode(&all_lengths);
+
+        let mut code_length_counts = vec![0u32; 19];
+        encoded
+            .iter()
+            .for_each(|&(s, _)| code_length_counts[s as usize] += 1);
+        let code_length_lengths: Vec<u8> = code_lengths(&code_length_counts, 7);
+        let code_length_codes: Vec<u16> = canonical_codes(&code_length_lengths);
+        let code_length_count: usize = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&s| code_length_lengths[s] > 0)
+                .unwrap()
+                + 1,
+        );
+
+        let mut header = BitWriter::new();
+        header.write((literal_count - 257) as u32, 5);
+        header.write((distance_count - 1) as u32, 5);
+        header.write((code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+            header.write(code_length_lengths[symbol] as u32, 3);
+        }
+        for (symbol, extra) in encoded {
+            let symbol = symbol as usize;
+            header.write_code(code_length_codes[symbol], code_length_lengths[symbol]);
+            match symbol {
+                16 => header.write(extra as u32, 2),
+                17 => header.write(extra as u32, 3),
+                18 => header.write(extra as u32, 7),
+                _ => {}
+            }
+        }
+
+        (literals, distances, header)
+    }
+
+    /// Write the block with whichever of stored, fixed or dynamic encoding is smallest.
+    fn write(&self, writer: &mut BitWriter, last: bool) {
+        let stored_cost: usize =
+            8 * (self.bytes.len() + 5 * self.bytes.len().div_ceil(MAX_STORED).max(1)) + 7;
+        let (fixed_literals, fixed_distances) = fixed_lengths();
+        let fixed_cost: usize = 3 + self.cost(&fixed_literals, &fixed_distances);
+
+        let (literals, distances, header) = self.dynamic_header();
+        let header_bits: usize = 8 * header.bytes.len() + header.count as usize;
+        let dynamic_cost: usize = 3 + header_bits + self.cost(&literals, &distances);
+
+        if stored_cost <= fixed_cost.min(dynamic_cost) {
+            self.write_stored(writer, last);
+        } else if fixed_cost <= dynamic_cost {
+            self.write_fixed(writer, last);
+        } else {
+            writer.write(last as u32, 1);
+            writer.write(2, 2);
+            for &byte in &header.bytes {
+                writer.write(byte as u32, 8);
+            }
+            writer.write(header.buffer as u32, header.count);
+            self.write_symbols(writer, &literals, &distances);
+        }
+    }
+}
+
+/// Compress `data` into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let symbols: Vec<Symbol> = lz77(data);
+    let mut writer = BitWriter::new();
+
+    // Compression method 8 with a 32K window, and check bits making the header a multiple of 31
+    writer.bytes.extend_from_slice(&[0x78, 0x9c]);
+
+    let mut offset: usize = 0;
+    let chunks: Vec<&[Symbol]> = match symbols.is_empty() {
+        true => vec![&[]],
+        false => symbols.chunks(BLOCK_SYMBOLS).collect(),
+    };
+    for (n, chunk) in chunks.iter().enumerate() {
+        let size: usize = chunk
+            .iter()
+            .map(|symbol| match symbol {
+                Symbol::Literal(_) => 1,
+                Symbol::Match { length, .. } => *length,
+            })
+            .sum();
+
+        let block = Block {
+            symbols: chunk,
+            bytes: &data[offset..offset + size],
+        };
+        block.write(&mut writer, n + 1 == chunks.len());
+        offset += size;
+    }
+
+    writer.align();
+    writer.bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    writer.bytes
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits from the start of `data`.
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, Error> {
+        let byte: u8 = *self
+            .data
+            .get(self.position / 8)
+            .ok_or_else(|| invalid("Unexpected end of compressed data"))?;
+        let bit = (byte >> (self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u32)
+    }
+
+    /// Read `count` bits, least significant bit first.
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        (0..count).try_fold(0, |value, n| Ok(value | self.bit()? << n))
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+/// Canonical Huffman decoding table, stored as the number of codes of each length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0u16; 16];
+        lengths.iter().for_each(|&l| counts[l as usize] += 1);
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err(invalid("Over-subscribed Huffman code"));
+            }
+        }
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&s| lengths[s as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, Error> {
+        let (mut code, mut first, mut index): (i32, i32, i32) = (0, 0, 0);
+
+        for &count in &self.counts[1..] {
+            code |= reader.bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("Invalid Huffman code"))
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid("Too many codes in dynamic block"));
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat): (u8, u32) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("Repeat with no previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err(invalid("Code lengths overflow dynamic block"));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(invalid("Missing end-of-block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    max_output: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), Error> {
+    loop {
+        if output.len() > max_output {
+            return Err(invalid("zlib data longer than expected"));
+        }
+        let symbol: usize = literals.decode(reader)?;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            END_OF_BLOCK => return Ok(()),
+            _ => {
+                let code: usize = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(invalid("Invalid length code"));
+                }
+                let length =
+                    LENGTH_BASE[code] as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+
+                let code: usize = distances.decode(reader)?;
+                if code >= DISTANCE_BASE.len() {
+                    return Err(invalid("Invalid distance code"));
+                }
+                let distance = DISTANCE_BASE[code] as usize
+                    + reader.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(invalid("Distance reaches before start of data"));
+                }
+
+                let start: usize = output.len() - distance;
+                (start..start + length).for_each(|i| output.push(output[i]));
+            }
+        }
+    }
+}
+
+/// Decompress a zlib stream, checking its header and checksum, and failing once it inflates to
+/// more than `max_output` bytes.
+pub fn decompress(data: &[u8], max_output: usize) -> Result<Vec<u8>, Error> {
+    let [method, flags] = *data
+        .first_chunk::<2>()
+        .ok_or_else(|| invalid("Missing zlib header"))?;
+    if method & 0x0f != 8 || !(method as u16 * 256 + flags as u16).is_multiple_of(31) {
+        return Err(invalid("Invalid zlib header"));
+    }
+    if flags & 0x20 != 0 {
+        return Err(invalid("Preset zlib dictionaries are not supported"));
+    }
+
+    let mut reader = BitReader {
+        data: &data[2..],
+        position: 0,
+    };
+    let mut output: Vec<u8> = Vec::new();
+
+    loop {
+        let last: bool = reader.bit()? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let start: usize = reader.position / 8;
+                let header: &[u8] = reader
+                    .data
+                    .get(start..start + 4)
+                    .ok_or_else(|| invalid("Unexpected end of stored block"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(invalid("Stored block length mismatch"));
+                }
+
+                let bytes: &[u8] = reader
+                    .data
+                    .get(start + 4..start + 4 + length as usize)
+                    .ok_or_else(|| invalid("Unexpected end of stored block"))?;
+                if output.len() + bytes.len() > max_output {
+                    return Err(invalid("zlib data longer than expected"));
+                }
+                output.extend_from_slice(bytes);
+                reader.position = 8 * (start + 4 + length as usize);
+            }
+            1 => {
+                let (literals, distances) = fixed_lengths();
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    max_output,
+                    &Huffman::new(&literals)?,
+                    &Huffman::new(&distances)?,
+                )?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut output, max_output, &literals, &distances)?;
+            }
+            _ => return Err(invalid("Invalid block type")),
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    // A last match may run past the limit before it is checked again
+    if output.len() > max_output {
+        return Err(invalid("zlib data longer than expected"));
+    }
+
+    reader.align();
+    let end: usize = reader.position / 8;
+    let checksum: [u8; 4] = reader
+        .data
+        .get(end..end + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| invalid("Missing zlib checksum"))?;
+    if u32::from_be_bytes(checksum) != adler32(&output) {
+        return Err(invalid("zlib checksum mismatch"));
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes with a skewed distribution, so that every block type gets exercised.
+    fn sample_data(length: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..length)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                match i % 7 {
+                    0 => (state >> 24) as u8,
+                    _ => b"tiny renderer"[(state >> 16) as usize % 13],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn code_lengths_respect_limit() {
+        // Fibonacci frequencies give the deepest possible Huffman tree
+        let mut frequencies: Vec<u32> = vec![1, 1];
+        while frequencies.len() < 30 {
+            let n = frequencies.len();
+            frequencies.push(frequencies[n - 1] + frequencies[n - 2]);
+        }
+
+        let lengths: Vec<u8> = code_lengths(&frequencies, 15);
+        let kraft: f64 = lengths.iter().map(|&l| 0.5_f64.powi(l as i32)).sum();
+        assert_eq!(lengths.iter().max(), Some(&15));
+        assert_eq!(kraft, 1.);
+
+        assert_eq!(code_lengths(&[0, 0, 5], 7), vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn test_canonical_codes() {
+        // Example from RFC 1951 section 3.2.2
+        assert_eq!(
+            canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]),
+            vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![42],
+            vec![7; 100_000],
+            sample_data(200_000),
+            (0..=255)
+                .cycle()
+                .take(70_000)
+                .map(|b: u32| (b * 97 % 256) as u8)
+                .collect(),
+        ];
+
+        for input in inputs {
+            let compressed: Vec<u8> = compress(&input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn decompress_reference_streams() {
+        // "tiny renderer tiny renderer" compressed by zlib with default and no compression
+        let fixed: [u8; 25] = [
+            0x78, 0x9c, 0x2b, 0xc9, 0xcc, 0xab, 0x54, 0x28, 0x4a, 0xcd, 0x4b, 0x49, 0x2d, 0x4a,
+            0x2d, 0x52, 0x28, 0x41, 0xe6, 0x01, 0x00, 0x93, 0xdf, 0x0a, 0x97,
+        ];
+        let stored: Vec<u8> = [
+            &[0x78, 0x01, 0x01, 0x1b, 0x00, 0xe4, 0xff][..],
+            b"tiny renderer tiny renderer",
+            &[0x93, 0xdf, 0x0a, 0x97],
+        ]
+        .concat();
+
+        for stream in [&fixed[..], &stored] {
+            assert_eq!(
+                decompress(stream, 27).unwrap(),
+                b"tiny renderer tiny renderer"
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_streams_are_rejected() {
+        let mut compressed: Vec<u8> = compress(&sample_data(1000));
+        assert!(decompress(&compressed[..compressed.len() - 1], 1000).is_err());
+
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress(&compressed, 1000).is_err());
+
+        assert!(decompress(&[0x78, 0x9d], 1000).is_err());
+    }
+
+    #[test]
+    fn output_is_limited() {
+        // A megabyte of zeros compresses to a few kilobytes
+        let zeros: Vec<u8> = compress(&vec![0; 1 << 20]);
+        assert!(zeros.len() < 1 << 14);
+        assert!(decompress(&zeros, 1 << 14).is_err());
+        assert!(decompress(&zeros, (1 << 20) - 1).is_err());
+        assert_eq!(decompress(&zeros, 1 << 20).unwrap().len(), 1 << 20);
+
+        let stored: Vec<u8> = [
+            &[0x78, 0x01, 0x01, 0x1b, 0x00, 0xe4, 0xff][..],
+            b"tiny renderer tiny renderer",
+            &[0x93, 0xdf, 0x0a, 0x97],
+        ]
+        .concat();
+        assert!(decompress(&stored, 26).is_err());
+    }
+}
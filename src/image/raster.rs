@@ -0,0 +1,519 @@
+use crate::math::vector::{Vec2d, Vec3d, Vec4d, dot_product, weighted_sum};
+
+/// Bits of sub-pixel precision that vertex positions are snapped to.
+pub const SUBPIXEL_BITS: u32 = 8;
+/// One pixel in fixed-point raster coordinates.
+pub(crate) const ONE: i64 = 1 << SUBPIXEL_BITS;
+
+/// Furthest a vertex may lie from the image origin, in pixels, for its triangle to be drawn.
+/// Within it, snapped coordinates fit in 30 bits and every edge function product and sum fits
+/// in an `i64`.
+pub const GUARD_BAND: f64 = (1 << (30 - SUBPIXEL_BITS)) as f64;
+
+/// Side of the square blocks of pixels whose edge functions are evaluated together.
+pub const BLOCK_SIZE: usize = 8;
+
+/// Direction in which the vertices of a triangle wind on screen, with `y` pointing up.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Winding {
+    #[default]
+    Anticlockwise,
+    Clockwise,
+}
+
+/// Which faces of triangles are discarded before rasterisation.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Cull {
+    /// Draw every triangle, whichever way it faces.
+    None,
+    Front,
+    #[default]
+    Back,
+}
+
+/// Point in fixed-point raster coordinates, measured from the bottom left of the image.
+pub(crate) type Fixed = [i64; 2];
+
+/// Fixed-point raster position of a continuous image position, rounded to the nearest step.
+pub(crate) fn snap(position: &Vec2d) -> Fixed {
+    position.map(|p| (p * ONE as f64).round() as i64)
+}
+
+/// Centre of the pixel at `x` and `y`.
+pub(crate) fn centre(x: usize, y: usize) -> Fixed {
+    [x as i64 * ONE + ONE / 2, y as i64 * ONE + ONE / 2]
+}
+
+/// Perspective-correct barycentric weights from the screen-space `weights` of a point and the
+/// clip-space `w` of each vertex. Attributes divided by `w` vary linearly across the screen, so
+/// each weight is divided by its vertex's `w` and the weights scaled back to sum to one.
+pub fn perspective_weights(weights: &Vec3d, w: &Vec3d) -> Vec3d {
+    let divided: Vec3d = [0, 1, 2].map(|n| weights[n] / w[n]);
+    let total: f64 = divided.iter().sum();
+    divided.map(|weight| weight / total)
+}
+
+/// The part of the triangle `clip` in front of the near plane, where `z <= w` in clip space, as
+/// a convex polygon of up to four vertices in the same winding. Each vertex comes with its
+/// weights over the vertices of `clip`, for mapping weights across the polygon back to them.
+pub(crate) fn clip_near(clip: &[Vec4d; 3]) -> Vec<(Vec4d, Vec3d)> {
+    let corners: [Vec3d; 3] = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+    let distance = |[_, _, z, w]: Vec4d| w - z;
+    let mut polygon: Vec<(Vec4d, Vec3d)> = Vec::with_capacity(4);
+
+    for n in 0..3 {
+        let (a, b) = (n, (n + 1) % 3);
+        let (near_a, near_b) = (distance(clip[a]), distance(clip[b]));
+        if near_a >= 0. {
+            polygon.push((clip[a], corners[a]));
+        }
+        // The edge crosses the plane, so the point where it does replaces the vertex beyond it
+        if (near_a >= 0.) != (near_b >= 0.) {
+            let t: f64 = near_a / (near_a - near_b);
+            let weights: Vec3d = weighted_sum(&[corners[a], corners[b]], &[1. - t, t]);
+            polygon.push((weighted_sum(clip, &weights), weights));
+        }
+    }
+    polygon
+}
+
+/// Twice the signed area of the triangle `p`, `q`, `r`, in squared fixed-point units. This is
+/// positive when `r` lies to the left of the line from `p` to `q`.
+fn edge_function(p: &Fixed, q: &Fixed, r: &Fixed) -> i64 {
+    (q[0] - p[0]) * (r[1] - p[1]) - (q[1] - p[1]) * (r[0] - p[0])
+}
+
+/// Edge function of one edge, as `step[0] * x + step[1] * y + offset`.
+#[derive(Copy, Clone, Debug)]
+struct Edge {
+    step: [i64; 2],
+    offset: i64,
+    /// Subtracted from the edge function before testing, so that points exactly on the edge are
+    /// only inside when it is a top or left edge.
+    bias: i64,
+}
+
+impl Edge {
+    fn new(from: &Fixed, to: &Fixed) -> Self {
+        let [dx, dy] = [to[0] - from[0], to[1] - from[1]];
+        let step: [i64; 2] = [-dy, dx];
+
+        // With anticlockwise winding and `y` up, the inside is to the left of each edge, so top
+        // edges run in -x and left edges run in -y
+        let top_left: bool = (dy == 0 && dx < 0) || dy < 0;
+
+        Self {
+            step,
+            offset: -(step[0] * from[0] + step[1] * from[1]),
+            bias: if top_left { 0 } else { 1 },
+        }
+    }
+
+    fn evaluate(&self, p: &Fixed) -> i64 {
+        self.step[0] * p[0] + self.step[1] * p[1] + self.offset
+    }
+}
+
+/// How much of a block of pixels a triangle covers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Coverage {
+    Outside,
+    Partial,
+    Full,
+}
+
+/// Part of a block of the `BLOCK_SIZE` grid that a triangle may cover.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Block {
+    /// Columns and rows of its pixels, end exclusive.
+    pub(crate) columns: [usize; 2],
+    pub(crate) rows: [usize; 2],
+    /// Whether the triangle covers the whole block.
+    full: bool,
+    /// Least and greatest depth the triangle has within the block.
+    pub(crate) depth: [f64; 2],
+}
+
+/// Triangle snapped to fixed-point raster coordinates, ready to have its edge functions
+/// evaluated. Clockwise triangles have their last two vertices swapped so that edge functions
+/// are positive inside, while weights are still given in the order the vertices came in.
+pub(crate) struct Setup {
+    vertices: [Fixed; 3],
+    winding: Winding,
+    /// Edges opposite each vertex, whose functions give that vertex's barycentric weight.
+    edges: [Edge; 3],
+    /// Twice the area of the triangle, the sum of its three edge functions at any point.
+    area: i64,
+    depths: Vec3d,
+}
+
+impl Setup {
+    /// Set up a triangle from its image positions and depths, or `None` if it has no area once
+    /// snapped or a vertex lies outside the guard band.
+    pub(crate) fn new(positions: [Vec2d; 3], depths: Vec3d) -> Option<Self> {
+        if !positions.iter().flatten().all(|p| p.abs() <= GUARD_BAND) {
+            return None;
+        }
+        Self::snapped(positions.map(|p| snap(&p)), depths)
+    }
+
+    fn snapped(vertices: [Fixed; 3], depths: Vec3d) -> Option<Self> {
+        let [a, b, c] = vertices;
+
+        let (vertices, winding) = match edge_function(&a, &b, &c) {
+            0 => return None,
+            area if area > 0 => ([a, b, c], Winding::Anticlockwise),
+            _ => ([a, c, b], Winding::Clockwise),
+        };
+        let [a, b, c] = &vertices;
+
+        Some(Self {
+            vertices,
+            winding,
+            edges: [Edge::new(b, c), Edge::new(c, a), Edge::new(a, b)],
+            area: edge_function(a, b, c),
+            depths,
+        })
+    }
+
+    pub(crate) fn winding(&self) -> Winding {
+        self.winding
+    }
+
+    /// The same triangle with the pixel at `x` and `y` moved to the origin. Edge functions are
+    /// exact, so they take the same values at the same pixels as before.
+    pub(crate) fn translated(&self, x: usize, y: usize) -> Self {
+        let offset: Fixed = [x as i64 * ONE, y as i64 * ONE];
+        let vertices: [Fixed; 3] = self.vertices.map(|v| [v[0] - offset[0], v[1] - offset[1]]);
+        Self {
+            winding: self.winding,
+            ..Self::snapped(vertices, self.depths).expect("Translation keeps the area")
+        }
+    }
+
+    /// Inclusive range of pixel columns and rows the triangle may touch, clipped to an image of
+    /// `width` by `height`, or `None` if it lies entirely outside it.
+    pub(crate) fn bounds(&self, width: usize, height: usize) -> Option<[[usize; 2]; 2]> {
+        let axis = |axis: usize, size: usize| {
+            let values = self.vertices.iter().map(|v| v[axis]);
+            let min: i64 = values.clone().min().unwrap().div_euclid(ONE).max(0);
+            let max: i64 = (values.max().unwrap() + ONE - 1)
+                .div_euclid(ONE)
+                .min(size as i64)
+                - 1;
+            (min <= max).then_some([min as usize, max as usize])
+        };
+
+        Some([axis(0, width)?, axis(1, height)?])
+    }
+
+    /// Values of the three edge functions at `p`.
+    pub(crate) fn edge_values(&self, p: &Fixed) -> [i64; 3] {
+        self.edges.map(|edge| edge.evaluate(p))
+    }
+
+    /// Amount the edge functions change by per pixel step right and up the image.
+    fn pixel_steps(&self) -> [[i64; 3]; 2] {
+        [0, 1].map(|axis| self.edges.map(|edge| edge.step[axis] * ONE))
+    }
+
+    /// Whether edge function values belong to a point inside the triangle, under the top-left
+    /// rule for points on its edges.
+    pub(crate) fn inside(&self, values: &[i64; 3]) -> bool {
+        (0..3).all(|n| values[n] - self.edges[n].bias >= 0)
+    }
+
+    /// Coverage of the rectangle of pixels with columns `x` and rows `y` (end exclusive), from
+    /// the edge functions at its four outer corners. Edge functions are linear, so a rectangle is
+    /// outside if all its corners are outside one edge, and covered if all are inside every edge.
+    /// Also gives the range of depths the triangle can have within the rectangle.
+    fn coverage(&self, x: [usize; 2], y: [usize; 2]) -> (Coverage, [f64; 2]) {
+        let corners: [Fixed; 4] = [[x[0], y[0]], [x[1], y[0]], [x[0], y[1]], [x[1], y[1]]]
+            .map(|[x, y]| [x as i64 * ONE, y as i64 * ONE]);
+        let values: [[i64; 3]; 4] = corners.map(|corner| self.edge_values(&corner));
+
+        let mut full: bool = true;
+        for (n, edge) in self.edges.iter().enumerate() {
+            let inside: usize = values.iter().filter(|v| v[n] - edge.bias >= 0).count();
+            match inside {
+                0 => return (Coverage::Outside, [0.; 2]),
+                4 => {}
+                _ => full = false,
+            }
+        }
+
+        // Depth is linear too, so over the triangle's part of the rectangle it lies between its
+        // values at the corners, as well as between its values at the vertices
+        let range = |depths: &[f64]| {
+            depths
+                .iter()
+                .fold([f64::INFINITY, f64::NEG_INFINITY], |[min, max], &d| {
+                    [min.min(d), max.max(d)]
+                })
+        };
+        let [near, far] = range(&values.map(|v| self.depth(&self.weights(&v))));
+        let [nearest, farthest] = range(&self.depths);
+        let depth: [f64; 2] = [near.max(nearest), far.min(farthest)];
+
+        match full {
+            true => (Coverage::Full, depth),
+            false => (Coverage::Partial, depth),
+        }
+    }
+
+    /// Call `visit` with every block of the `BLOCK_SIZE` grid within `bounds` that the triangle
+    /// may cover, clipped to `bounds`. Blocks outside the triangle are skipped whole.
+    pub(crate) fn blocks<V>(&self, bounds: [[usize; 2]; 2], mut visit: V)
+    where
+        V: FnMut(&Block),
+    {
+        let [[left, right], [bottom, top]] = bounds;
+        let aligned = |start: usize| start - start % BLOCK_SIZE;
+
+        for y in (aligned(bottom)..=top).step_by(BLOCK_SIZE) {
+            let rows: [usize; 2] = [y.max(bottom), (y + BLOCK_SIZE).min(top + 1)];
+            for x in (aligned(left)..=right).step_by(BLOCK_SIZE) {
+                let columns: [usize; 2] = [x.max(left), (x + BLOCK_SIZE).min(right + 1)];
+                let (coverage, depth) = self.coverage(columns, rows);
+                if coverage != Coverage::Outside {
+                    visit(&Block {
+                        columns,
+                        rows,
+                        full: coverage == Coverage::Full,
+                        depth,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Call `visit` with the column, row and edge function values of every pixel of `block`
+    /// whose centre the triangle covers, testing a row of lanes at a time.
+    pub(crate) fn covered_pixels<V>(&self, block: &Block, mut visit: V)
+    where
+        V: FnMut(usize, usize, &[i64; 3]),
+    {
+        let [step_x, step_y] = self.pixel_steps();
+        let bias: [i64; 3] = self.edges.map(|edge| edge.bias);
+        let [columns, rows] = [block.columns, block.rows];
+        let mut row: [i64; 3] = self.edge_values(&centre(columns[0], rows[0]));
+
+        for y in rows[0]..rows[1] {
+            let lanes: [[i64; BLOCK_SIZE]; 3] = std::array::from_fn(|n| {
+                std::array::from_fn(|lane| row[n] + step_x[n] * lane as i64)
+            });
+            let mask: [bool; BLOCK_SIZE] = std::array::from_fn(|lane| {
+                block.full || (0..3).all(|n| lanes[n][lane] - bias[n] >= 0)
+            });
+
+            for (lane, x) in (columns[0]..columns[1]).enumerate() {
+                if mask[lane] {
+                    visit(x, y, &[0, 1, 2].map(|n| lanes[n][lane]));
+                }
+            }
+            (0..3).for_each(|n| row[n] += step_y[n]);
+        }
+    }
+
+    /// Call `visit` with the column, row and edge function values at the centre of every pixel
+    /// within `bounds` that the triangle may cover any part of.
+    pub(crate) fn touched_pixels<V>(&self, bounds: [[usize; 2]; 2], mut visit: V)
+    where
+        V: FnMut(usize, usize, &[i64; 3]),
+    {
+        self.blocks(bounds, |block| {
+            for y in block.rows[0]..block.rows[1] {
+                for x in block.columns[0]..block.columns[1] {
+                    visit(x, y, &self.edge_values(&centre(x, y)));
+                }
+            }
+        });
+    }
+
+    /// Barycentric weights of a point with the given edge function values, in the order the
+    /// vertices were given.
+    pub(crate) fn weights(&self, values: &[i64; 3]) -> Vec3d {
+        let [a, b, c] = values.map(|value| value as f64 / self.area as f64);
+        match self.winding {
+            Winding::Anticlockwise => [a, b, c],
+            Winding::Clockwise => [a, c, b],
+        }
+    }
+
+    /// Depth interpolated with barycentric `weights`.
+    pub(crate) fn depth(&self, weights: &Vec3d) -> f64 {
+        dot_product(weights, &self.depths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_triangles_are_rejected() {
+        let depths: Vec3d = [0.; 3];
+
+        assert!(Setup::new([[0., 0.], [4., 0.], [0., 4.]], depths).is_some());
+        // Collinear once snapped to the sub-pixel grid
+        assert!(Setup::new([[0., 0.], [4., 0.], [8., 0.001]], depths).is_none());
+    }
+
+    #[test]
+    fn top_left_rule() {
+        // Right triangle whose left and bottom edges run through pixel centres
+        let setup = Setup::new([[0.5, 0.5], [4.5, 0.5], [0.5, 4.5]], [0.; 3]).unwrap();
+        let inside = |x: usize, y: usize| setup.inside(&setup.edge_values(&centre(x, y)));
+
+        // The left edge is included, the bottom edge is not, nor is the hypotenuse
+        assert!(inside(0, 1) && inside(0, 3));
+        assert!(!inside(1, 0) && !inside(3, 0));
+        assert!(inside(1, 1) && !inside(2, 2) && !inside(0, 4));
+    }
+
+    #[test]
+    fn bounds_are_clipped() {
+        let setup = Setup::new([[-3., 1.2], [5.5, 1.2], [2., 9.]], [0.; 3]).unwrap();
+
+        assert_eq!(setup.bounds(4, 4), Some([[0, 3], [1, 3]]));
+        assert_eq!(setup.bounds(100, 100), Some([[0, 5], [1, 8]]));
+        assert_eq!(
+            Setup::new([[10., 10.], [12., 10.], [10., 12.]], [0.; 3])
+                .unwrap()
+                .bounds(4, 4),
+            None
+        );
+    }
+
+    #[test]
+    fn triangles_are_clipped_to_the_near_plane() {
+        let inside: [Vec4d; 3] = [[0., 0., 0., 1.], [1., 0., 0., 1.], [0., 1., 0., 1.]];
+        let clipped: Vec<(Vec4d, Vec3d)> = clip_near(&inside);
+        assert_eq!(clipped.len(), 3);
+        assert!(
+            clipped
+                .iter()
+                .zip(inside)
+                .all(|((vertex, _), v)| *vertex == v)
+        );
+
+        // The last vertex is beyond the plane, leaving a quad cut halfway along two edges
+        let crossing: [Vec4d; 3] = [[0., 0., 0., 1.], [1., 0., 0., 1.], [0., 1., 1., 0.]];
+        let clipped: Vec<(Vec4d, Vec3d)> = clip_near(&crossing);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|([_, _, z, w], _)| z <= w));
+        assert_eq!(clipped[2].1, [0., 0.5, 0.5]);
+        assert_eq!(clipped[3].1, [0.5, 0., 0.5]);
+        for (vertex, weights) in &clipped {
+            assert_eq!(*vertex, weighted_sum(&crossing, weights));
+        }
+
+        assert!(clip_near(&crossing.map(|[x, y, _, w]| [x, y, 2., w])).is_empty());
+    }
+
+    #[test]
+    fn vertices_outside_the_guard_band_are_rejected() {
+        let depths: Vec3d = [0.; 3];
+
+        assert!(Setup::new([[0., 0.], [4., 0.], [0., 1e12]], depths).is_none());
+        assert!(Setup::new([[0., 0.], [4., 0.], [0., f64::NAN]], depths).is_none());
+
+        // The largest triangle allowed is rasterised without overflowing
+        let g: f64 = GUARD_BAND;
+        let setup = Setup::new([[-g, -g], [g, 0.], [0., g]], depths).unwrap();
+        let mut covered: usize = 0;
+        setup.blocks(setup.bounds(16, 16).unwrap(), |block| {
+            setup.covered_pixels(block, |_, _, _| covered += 1);
+        });
+        assert_eq!(covered, 16 * 16);
+    }
+
+    #[test]
+    fn blocks_match_testing_every_pixel() {
+        let triangles: [[Vec2d; 3]; 4] = [
+            [[0.5, 0.5], [60., 3.], [30., 45.]],
+            // Long and thin, crossing many blocks it barely touches
+            [[1., 1.], [63., 62.3], [62., 63.]],
+            [[-20., -20.], [100., -20.], [-20., 100.]],
+            [[10.5, 10.5], [12.5, 10.5], [10.5, 12.5]],
+        ];
+
+        for positions in triangles {
+            let setup = Setup::new(positions, [0.; 3]).unwrap();
+            let bounds = setup.bounds(64, 64).unwrap();
+            let [[left, right], [bottom, top]] = bounds;
+
+            let mut expected: Vec<(usize, usize)> = Vec::new();
+            for y in bottom..=top {
+                for x in left..=right {
+                    if setup.inside(&setup.edge_values(&centre(x, y))) {
+                        expected.push((x, y));
+                    }
+                }
+            }
+
+            let mut covered: Vec<(usize, usize)> = Vec::new();
+            setup.blocks(bounds, |block| {
+                setup.covered_pixels(block, |x, y, values| {
+                    assert_eq!(*values, setup.edge_values(&centre(x, y)));
+                    covered.push((x, y));
+                });
+            });
+            covered.sort_by_key(|&(x, y)| (y, x));
+            assert_eq!(covered, expected, "{positions:?}");
+        }
+    }
+
+    #[test]
+    fn blocks_outside_the_triangle_are_rejected() {
+        let setup = Setup::new([[1., 1.], [63., 62.3], [62., 63.]], [0.; 3]).unwrap();
+        let bounds = setup.bounds(64, 64).unwrap();
+
+        let mut touched: usize = 0;
+        setup.touched_pixels(bounds, |_, _, _| touched += 1);
+        // About the 22 blocks along the diagonal, out of the 64 in its bounds
+        assert!(touched <= 22 * BLOCK_SIZE * BLOCK_SIZE, "{touched}");
+
+        assert_eq!(setup.coverage([0, 8], [56, 64]).0, Coverage::Outside);
+        assert_eq!(setup.coverage([56, 64], [56, 64]).0, Coverage::Partial);
+        let large = Setup::new([[-20., -20.], [100., -20.], [-20., 100.]], [0.; 3]).unwrap();
+        assert_eq!(large.coverage([0, 8], [0, 8]).0, Coverage::Full);
+    }
+
+    #[test]
+    fn block_depth_ranges() {
+        // Depth rises from 0 at the left to 1 at the right, and the triangle ends at x = 32
+        let setup = Setup::new([[0., 0.], [32., 0.], [0., 64.]], [0., 1., 0.]).unwrap();
+
+        let [near, far] = setup.coverage([8, 16], [0, 8]).1;
+        assert!((near - 0.25).abs() < 1e-12 && (far - 0.5).abs() < 1e-12);
+        // Clamped to the depths of the vertices where the block reaches past the triangle
+        assert_eq!(setup.coverage([26, 34], [0, 4]).1[1], 1.);
+    }
+
+    #[test]
+    fn weights_and_depth_at_a_point() {
+        let setup = Setup::new([[0., 0.], [4., 0.], [0., 4.]], [0., 1., 0.5]).unwrap();
+        let weights: Vec3d = setup.weights(&setup.edge_values(&centre(1, 1)));
+
+        assert_eq!(weights, [0.25, 0.375, 0.375]);
+        assert_eq!(setup.depth(&weights), 0.5625);
+    }
+
+    #[test]
+    fn clockwise_triangles_keep_their_vertex_order() {
+        let anticlockwise = Setup::new([[0., 0.], [4., 0.], [0., 4.]], [0., 1., 0.5]).unwrap();
+        let clockwise = Setup::new([[0., 0.], [0., 4.], [4., 0.]], [0., 0.5, 1.]).unwrap();
+        assert_eq!(anticlockwise.winding(), Winding::Anticlockwise);
+        assert_eq!(clockwise.winding(), Winding::Clockwise);
+
+        for (x, y) in [(1, 1), (0, 2), (3, 0), (2, 2)] {
+            let [a, b] = [&anticlockwise, &clockwise].map(|s| s.edge_values(&centre(x, y)));
+            assert_eq!(anticlockwise.inside(&a), clockwise.inside(&b));
+
+            let [u, v, w] = anticlockwise.weights(&a);
+            assert_eq!(clockwise.weights(&b), [u, w, v]);
+        }
+        assert_eq!(clockwise.translated(1, 0).winding(), Winding::Clockwise);
+    }
+}
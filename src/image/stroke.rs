@@ -0,0 +1,373 @@
+use crate::image::blend::Blend;
+use crate::image::pixel::Pixel;
+use crate::image::{Image, Position};
+use crate::math::vector::{Vec2d, add, dot_product, length, scalar_mul, sub, unit};
+
+/// Longest a mitre may reach from its vertex, in half line widths, before it is bevelled.
+const MITRE_LIMIT: f64 = 4.;
+
+/// Shape of the two ends of an open polyline.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Cap {
+    /// Stop at the end point.
+    Butt,
+    /// Extend past the end point by half the width.
+    Square,
+    /// Finish with a half disc around the end point.
+    Round,
+}
+
+/// Shape of the outside corner where two segments of a polyline meet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Join {
+    /// Extend the outer edges until they meet, bevelling corners sharper than the mitre limit.
+    Mitre,
+    /// Cut the corner off straight.
+    Bevel,
+    /// Round the corner off with a disc.
+    Round,
+}
+
+/// How thick lines are drawn by `Image::polyline`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stroke {
+    /// Width in pixels.
+    pub width: f64,
+    pub cap: Cap,
+    pub join: Join,
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Self {
+            width: 1.,
+            cap: Cap::Butt,
+            join: Join::Mitre,
+        }
+    }
+}
+
+/// Piece of a stroke, whose union with the others makes up the whole line.
+enum Shape {
+    /// Convex polygon, with its corners in either winding order.
+    Polygon(Vec<Vec2d>),
+    Disc(Vec2d, f64),
+}
+
+impl Shape {
+    /// Signed distance from `point` to the edge of the shape, negative inside. Near a polygon's
+    /// corners this underestimates the distance, which only matters well away from the line.
+    fn distance(&self, point: &Vec2d) -> f64 {
+        match self {
+            Shape::Disc(centre, radius) => length(&sub(point, centre)) - radius,
+            Shape::Polygon(corners) => {
+                let doubled_area: f64 = (0..corners.len())
+                    .map(|n| cross(&corners[n], &corners[(n + 1) % corners.len()]))
+                    .sum();
+                let winding: f64 = doubled_area.signum();
+
+                (0..corners.len())
+                    .map(|n| {
+                        let (a, b) = (&corners[n], &corners[(n + 1) % corners.len()]);
+                        let edge: Vec2d = unit(&sub(b, a));
+                        let outward: Vec2d = scalar_mul(&[edge[1], -edge[0]], winding);
+                        dot_product(&sub(point, a), &outward)
+                    })
+                    .fold(f64::NEG_INFINITY, f64::max)
+            }
+        }
+    }
+
+    fn corners(&self) -> Vec<Vec2d> {
+        match self {
+            Shape::Polygon(corners) => corners.clone(),
+            Shape::Disc(centre, radius) => {
+                vec![sub(centre, &[*radius; 2]), add(centre, &[*radius; 2])]
+            }
+        }
+    }
+}
+
+fn cross(a: &Vec2d, b: &Vec2d) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+/// Normal to the left of `direction`.
+fn left(direction: &Vec2d) -> Vec2d {
+    [-direction[1], direction[0]]
+}
+
+/// Fractional part of `x`.
+fn fpart(x: f64) -> f64 {
+    x - x.floor()
+}
+
+impl Image {
+    /// Blend `colour` over the pixel at `x` and `y` in proportion to `coverage`, ignoring
+    /// positions outside the image.
+    fn cover(&mut self, colour: Pixel, x: i64, y: i64, coverage: f64) {
+        if x < 0 || y < 0 || coverage <= 0. {
+            return;
+        }
+
+        let position = Position {
+            x: x as usize,
+            y: y as usize,
+        };
+        if let Some(below) = self.get(&position) {
+            let source = Pixel {
+                alpha: (colour.alpha as f64 * coverage.min(1.)).round() as u8,
+                ..colour
+            };
+            self.set(Blend::AlphaOver.apply(source, below), &position);
+        }
+    }
+
+    /// One pixel wide anti-aliased line between two points, using Xiaolin Wu's algorithm.
+    /// Coordinates are in pixels from the image's origin, with pixel `(x, y)` covering
+    /// `[x, x + 1)` by `[y, y + 1)`.
+    pub fn smooth_line(&mut self, colour: Pixel, start: Vec2d, end: Vec2d) {
+        // Wu's algorithm places pixel centres on integer coordinates
+        let [mut x0, mut y0] = sub(&start, &[0.5; 2]);
+        let [mut x1, mut y1] = sub(&end, &[0.5; 2]);
+
+        let steep: bool = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            (x0, y0, x1, y1) = (y0, x0, y1, x1);
+        }
+        if x0 > x1 {
+            (x0, y0, x1, y1) = (x1, y1, x0, y0);
+        }
+
+        let plot = |image: &mut Self, x: f64, y: f64, coverage: f64| match steep {
+            true => image.cover(colour, y as i64, x as i64, coverage),
+            false => image.cover(colour, x as i64, y as i64, coverage),
+        };
+
+        let gradient: f64 = match x1 - x0 {
+            0. => 1.,
+            dx => (y1 - y0) / dx,
+        };
+
+        // Each end point covers its pixel in proportion to how far the line extends across it
+        let mut ends =
+            [(x0, y0, 1. - fpart(x0 + 0.5)), (x1, y1, fpart(x1 + 0.5))].map(|(x, y, gap)| {
+                let x_end: f64 = (x + 0.5).floor();
+                let y_end: f64 = y + gradient * (x_end - x);
+                (x_end, y_end, gap)
+            });
+        if ends[0].0 == ends[1].0 {
+            // Both ends fall in the same column, which they share
+            ends[1].2 = (x1 - x0).min(1.);
+            ends[0].2 = 0.;
+        }
+        for (x, y, gap) in ends {
+            plot(self, x, y.floor(), (1. - fpart(y)) * gap);
+            plot(self, x, y.floor() + 1., fpart(y) * gap);
+        }
+
+        let (first, last) = (ends[0].0 as i64, ends[1].0 as i64);
+        let mut y: f64 = ends[0].1 + gradient;
+        for x in first + 1..last {
+            plot(self, x as f64, y.floor(), 1. - fpart(y));
+            plot(self, x as f64, y.floor() + 1., fpart(y));
+            y += gradient;
+        }
+    }
+
+    /// Anti-aliased line through `points` of any width, in the coordinates used by
+    /// `smooth_line`. Every pixel is blended once, however many segments overlap it.
+    pub fn polyline(&mut self, colour: Pixel, points: &[Vec2d], stroke: &Stroke) {
+        let half_width: f64 = stroke.width / 2.;
+        let points: Vec<Vec2d> = points
+            .iter()
+            .enumerate()
+            .filter(|&(n, p)| n == 0 || *p != points[n - 1])
+            .map(|(_, p)| *p)
+            .collect();
+        if points.len() < 2 {
+            return;
+        }
+
+        let last: usize = points.len() - 2;
+        let mut shapes: Vec<Shape> = Vec::new();
+
+        for (n, segment) in points.windows(2).enumerate() {
+            let (mut start, mut end) = (segment[0], segment[1]);
+            let direction: Vec2d = unit(&sub(&end, &start));
+            let extension: Vec2d = scalar_mul(&direction, half_width);
+
+            if stroke.cap == Cap::Square {
+                if n == 0 {
+                    start = sub(&start, &extension);
+                }
+                if n == last {
+                    end = add(&end, &extension);
+                }
+            }
+
+            let side: Vec2d = scalar_mul(&left(&direction), half_width);
+            shapes.push(Shape::Polygon(vec![
+                add(&start, &side),
+                add(&end, &side),
+                sub(&end, &side),
+                sub(&start, &side),
+            ]));
+        }
+
+        if stroke.cap == Cap::Round {
+            shapes.push(Shape::Disc(points[0], half_width));
+            shapes.push(Shape::Disc(points[points.len() - 1], half_width));
+        }
+
+        for corner in points.windows(3) {
+            let [before, vertex, after] = [corner[0], corner[1], corner[2]];
+            let incoming: Vec2d = unit(&sub(&vertex, &before));
+            let outgoing: Vec2d = unit(&sub(&after, &vertex));
+            let turn: f64 = cross(&incoming, &outgoing);
+            if turn.abs() < 1e-9 {
+                continue;
+            }
+
+            // The gap to fill is on the outside of the turn
+            let outside: f64 = -turn.signum();
+            let normals: [Vec2d; 2] =
+                [incoming, outgoing].map(|d| scalar_mul(&left(&d), outside * half_width));
+            let edges: [Vec2d; 2] = normals.map(|normal| add(&vertex, &normal));
+
+            let bevel = Shape::Polygon(vec![vertex, edges[0], edges[1]]);
+            shapes.push(match stroke.join {
+                Join::Round => Shape::Disc(vertex, half_width),
+                Join::Bevel => bevel,
+                Join::Mitre => {
+                    let mitre: Vec2d = unit(&add(&normals[0], &normals[1]));
+                    let reach: f64 = half_width / dot_product(&mitre, &unit(&normals[0]));
+                    match reach <= MITRE_LIMIT * half_width {
+                        true => Shape::Polygon(vec![
+                            vertex,
+                            edges[0],
+                            add(&vertex, &scalar_mul(&mitre, reach)),
+                            edges[1],
+                        ]),
+                        false => bevel,
+                    }
+                }
+            });
+        }
+
+        let corners: Vec<Vec2d> = shapes.iter().flat_map(Shape::corners).collect();
+        let bound = |axis: usize, f: fn(f64, f64) -> f64, initial: f64| {
+            corners.iter().map(|c| c[axis]).fold(initial, f)
+        };
+        let (left, right) = (bound(0, f64::min, f64::MAX), bound(0, f64::max, f64::MIN));
+        let (bottom, top) = (bound(1, f64::min, f64::MAX), bound(1, f64::max, f64::MIN));
+
+        let columns = (left.floor().max(0.) as i64)..=(right.ceil().min(self.width as f64) as i64);
+        for y in (bottom.floor().max(0.) as i64)..=(top.ceil().min(self.height as f64) as i64) {
+            for x in columns.clone() {
+                let centre: Vec2d = [x as f64 + 0.5, y as f64 + 0.5];
+                let distance: f64 = shapes
+                    .iter()
+                    .map(|shape| shape.distance(&centre))
+                    .fold(f64::INFINITY, f64::min);
+                self.cover(colour, x, y, (0.5 - distance).clamp(0., 1.));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::pixel::WHITE;
+
+    fn red(image: &Image, x: usize, y: usize) -> u8 {
+        image.get(&Position { x, y }).unwrap().red
+    }
+
+    #[test]
+    fn smooth_lines_split_coverage_between_rows() {
+        let mut image = Image::blank(8, 4);
+
+        // Through the centres of row 1, then midway between rows 2 and 3
+        image.smooth_line(WHITE, [0.5, 1.5], [7.5, 1.5]);
+        image.smooth_line(WHITE, [0.5, 3.], [7.5, 3.]);
+
+        for x in 1..7 {
+            assert_eq!(red(&image, x, 1), 255);
+            assert_eq!(red(&image, x, 0), 0);
+            assert_eq!([red(&image, x, 2), red(&image, x, 3)], [128, 128]);
+        }
+        // Ends stop at pixel centres, so cover half of their pixel
+        assert_eq!(red(&image, 0, 1), 128);
+    }
+
+    #[test]
+    fn steep_smooth_lines() {
+        let mut image = Image::blank(4, 8);
+        image.smooth_line(WHITE, [2.5, 7.5], [2.5, 0.5]);
+
+        for y in 1..7 {
+            assert_eq!(red(&image, 2, y), 255);
+            assert_eq!(red(&image, 1, y), 0);
+        }
+    }
+
+    #[test]
+    fn thick_lines_and_caps() {
+        let stroke = |cap: Cap| Stroke {
+            width: 3.,
+            cap,
+            ..Default::default()
+        };
+
+        let mut butt = Image::blank(12, 5);
+        butt.polyline(WHITE, &[[3., 2.5], [9., 2.5]], &stroke(Cap::Butt));
+        assert!((1..4).all(|y| red(&butt, 3, y) == 255));
+        assert_eq!(
+            [red(&butt, 3, 0), red(&butt, 2, 2), red(&butt, 9, 2)],
+            [0; 3]
+        );
+
+        let mut square = Image::blank(12, 5);
+        square.polyline(WHITE, &[[3., 2.5], [9., 2.5]], &stroke(Cap::Square));
+        assert_eq!([red(&square, 2, 2), red(&square, 9, 2)], [255; 2]);
+        assert_eq!([red(&square, 1, 2), red(&square, 10, 2)], [128; 2]);
+
+        let mut round = Image::blank(12, 5);
+        round.polyline(WHITE, &[[3., 2.5], [9., 2.5]], &stroke(Cap::Round));
+        assert_eq!(red(&round, 2, 2), 255);
+        assert!(red(&round, 1, 1) < red(&square, 1, 1));
+    }
+
+    #[test]
+    fn joins_fill_the_outside_corner_once() {
+        // A right angle turning at (4, 4), whose outer corner is the pixel at (5, 5)
+        let points: [Vec2d; 3] = [[0., 4.], [4., 4.], [4., 0.]];
+        let corner = |join: Join| {
+            let mut image = Image::blank(8, 8);
+            let colour = Pixel {
+                alpha: 128,
+                ..WHITE
+            };
+            image.polyline(
+                colour,
+                &points,
+                &Stroke {
+                    width: 4.,
+                    join,
+                    ..Default::default()
+                },
+            );
+            (red(&image, 5, 5), red(&image, 3, 3))
+        };
+
+        let (mitre, inside) = corner(Join::Mitre);
+        let (bevel, _) = corner(Join::Bevel);
+        let (round, _) = corner(Join::Round);
+
+        // Overlapping segments inside the corner are blended only once
+        assert_eq!((mitre, inside), (128, 128));
+        assert!(bevel < round && round < mitre, "{bevel} {round} {mitre}");
+    }
+}
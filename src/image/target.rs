@@ -0,0 +1,217 @@
+use std::convert::identity;
+
+use crate::geometry::Vertex;
+use crate::image::pixel::Pixel;
+use crate::image::{Image, Position};
+use crate::math::vector::Vec3d;
+
+/// Values written per pixel alongside the colour, such as normals or albedo, in the same row
+/// order as the image's pixels.
+pub struct Attachment {
+    width: usize,
+    height: usize,
+    channels: usize,
+    values: Vec<f32>,
+}
+
+impl Attachment {
+    pub fn new(width: usize, height: usize, channels: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels,
+            values: vec![0.; width * height * channels],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Every value, `channels` per pixel.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill(0.);
+    }
+
+    /// Values of the pixel at `index`.
+    pub(crate) fn get(&self, index: usize) -> &[f32] {
+        &self.values[index * self.channels..(index + 1) * self.channels]
+    }
+
+    /// Store as many of `values` as there are channels, leaving any other channels as they are.
+    fn set(&mut self, index: usize, values: &[f64]) {
+        let texel = &mut self.values[index * self.channels..(index + 1) * self.channels];
+        for (channel, &value) in texel.iter_mut().zip(values) {
+            *channel = value as f32;
+        }
+    }
+}
+
+/// Attachments a fragment writes to, besides the colour it returns.
+pub struct Outputs<'a> {
+    attachments: &'a mut [(String, Attachment)],
+    index: usize,
+}
+
+impl Outputs<'_> {
+    /// Write `values` to the attachment called `name`, if the image has one.
+    pub fn set(&mut self, name: &str, values: &[f64]) {
+        if let Some((_, attachment)) = self.attachments.iter_mut().find(|(n, _)| n == name) {
+            attachment.set(self.index, values);
+        }
+    }
+}
+
+/// Attachments and depth of one pixel, read by a deferred pass.
+pub struct Inputs<'a> {
+    attachments: &'a [(String, Attachment)],
+    index: usize,
+    pub depth: f64,
+}
+
+impl Inputs<'_> {
+    /// Values of the attachment called `name`, if the image has one.
+    pub fn get(&self, name: &str) -> Option<&[f32]> {
+        self.attachments
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, attachment)| attachment.get(self.index))
+    }
+}
+
+impl Image {
+    /// Add an attachment called `name` with `channels` values per pixel, replacing any of the
+    /// same name.
+    pub fn add_attachment(&mut self, name: &str, channels: usize) {
+        let attachment = Attachment::new(self.width, self.height, channels);
+        match self.attachments.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = attachment,
+            None => self.attachments.push((name.to_string(), attachment)),
+        }
+    }
+
+    pub fn attachment(&self, name: &str) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, attachment)| attachment)
+    }
+
+    /// Values of the attachment called `name` at `position`, if there is such an attachment and
+    /// the position is within the image.
+    pub fn attachment_value(&self, name: &str, position: &Position) -> Option<&[f32]> {
+        let index: usize = self.index(position)?;
+        self.attachment(name)
+            .map(|attachment| attachment.get(index))
+    }
+
+    /// Rasterise a triangle as `shaded_triangle` does, with `fragment` also given the outputs
+    /// of its pixel to write attachments to. Attachments hold one value per pixel, so when
+    /// multisampling they are written by every triangle covering any sample that passes.
+    pub fn gbuffer_triangle<F>(&mut self, i: &Vertex, j: &Vertex, k: &Vertex, mut fragment: F)
+    where
+        F: FnMut(Vec3d, &mut Outputs) -> Pixel,
+    {
+        if let Some(setup) = self.setup(i, j, k) {
+            self.shade(&setup, identity, |attachments, index, barycentric| {
+                fragment(barycentric, &mut Outputs { attachments, index })
+            });
+        }
+    }
+
+    /// Overwrite every pixel that a triangle has been drawn to, by its depth, with the colour
+    /// `shade` returns from its attachments. When multisampling, depth is only available once
+    /// resolved.
+    pub fn deferred<F>(&mut self, mut shade: F)
+    where
+        F: FnMut(&Inputs) -> Pixel,
+    {
+        let clear_value: f64 = self.zbuffer.clear_value();
+        for (index, &depth) in self.zbuffer.values().iter().enumerate() {
+            if depth != clear_value {
+                let inputs = Inputs {
+                    attachments: &self.attachments,
+                    index,
+                    depth,
+                };
+                self.data[index] = shade(&inputs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Pixel = Pixel {
+        red: 0,
+        green: 0,
+        blue: 0,
+        alpha: 255,
+    };
+
+    #[test]
+    fn attachments_are_written_and_shaded_later() {
+        let corners: [Vertex; 4] =
+            [[-1., -1., 0.], [1., -1., 0.], [1., 1., 0.], [-1., 1., 0.]].map(Vertex::from);
+
+        let mut image = Image::blank(8, 8);
+        image.add_attachment("normal", 3);
+        image.add_attachment("albedo", 3);
+        // Only the lower right half of the image is covered
+        image.gbuffer_triangle(&corners[0], &corners[1], &corners[2], |_, outputs| {
+            outputs.set("normal", &[0., 0., 1.]);
+            outputs.set("albedo", &[0.5, 0.25, 1.]);
+            outputs.set("missing", &[1.]);
+            BLACK
+        });
+
+        let covered = Position { x: 6, y: 1 };
+        let uncovered = Position { x: 1, y: 6 };
+        assert_eq!(
+            image.attachment_value("normal", &covered),
+            Some(&[0., 0., 1.][..])
+        );
+        assert_eq!(
+            image.attachment_value("albedo", &uncovered),
+            Some(&[0.; 3][..])
+        );
+        assert_eq!(image.attachment_value("missing", &covered), None);
+        assert_eq!(
+            image.attachment("albedo").unwrap().values().len(),
+            8 * 8 * 3
+        );
+
+        // Lambertian shading lit from the viewer, from the albedo and normal alone
+        image.deferred(|inputs| {
+            let lighting: f32 = inputs.get("normal").unwrap()[2].max(0.);
+            let albedo: &[f32] = inputs.get("albedo").unwrap();
+            let [red, green, blue] = [0, 1, 2].map(|c| (albedo[c] * lighting * 255.) as f64);
+            [red, green, blue, 255.].into()
+        });
+
+        assert_eq!(
+            image.get(&covered),
+            Some(Pixel {
+                red: 128,
+                green: 64,
+                blue: 255,
+                alpha: 255,
+            })
+        );
+        assert_eq!(image.get(&uncovered), Some(BLACK));
+    }
+}
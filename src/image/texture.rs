@@ -0,0 +1,356 @@
+use crate::image::Image;
+use crate::image::pixel::Pixel;
+use crate::math::vector::{Vec2d, Vec4d, add, length, scalar_mul, weighted_sum};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Bilinear samples from the two mip levels closest to the sampling footprint, blended.
+    Trilinear,
+    /// Up to the given number of trilinear samples taken along the major axis of the footprint.
+    Anisotropic(usize),
+}
+
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Pixel>,
+}
+
+/// Texels of a side `size` long, and their weights, that make up texel `index` of the side
+/// once halved to `half`. Odd sides take three texels, each weighted by how much of it the new
+/// texel covers, so that every texel contributes.
+fn footprint(size: usize, half: usize, index: usize) -> Vec<(usize, f64)> {
+    match size {
+        1 => vec![(0, 1.)],
+        _ if size.is_multiple_of(2) => vec![(2 * index, 0.5), (2 * index + 1, 0.5)],
+        _ => [half - index, half, index + 1]
+            .into_iter()
+            .enumerate()
+            .map(|(n, covered)| (2 * index + n, covered as f64 / size as f64))
+            .collect(),
+    }
+}
+
+impl Level {
+    /// Halve the level with a box filter, rounding sizes down, widened to three texels along
+    /// odd sides. A side of a single texel stays one texel.
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = footprint(self.height, height, y);
+            for x in 0..width {
+                let columns = footprint(self.width, width, x);
+                let colour: Vec4d = rows.iter().fold([0.; 4], |total, &(sy, wy)| {
+                    columns.iter().fold(total, |total, &(sx, wx)| {
+                        let texel: Vec4d = self.texels[sx + self.width * sy].into();
+                        add(&total, &scalar_mul(&texel, wx * wy))
+                    })
+                });
+                texels.push(colour.into());
+            }
+        }
+
+        Level {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+/// Image sampled with normalised UV coordinates, with `v` pointing up from the last row of texels.
+pub struct Texture {
+    levels: Vec<Level>,
+    pub wrap: Wrap,
+    pub filter: Filter,
+}
+
+fn wrap_index(index: i64, size: usize, wrap: Wrap) -> usize {
+    let size = size as i64;
+    let wrapped = match wrap {
+        Wrap::Repeat => index.rem_euclid(size),
+        Wrap::Clamp => index.clamp(0, size - 1),
+        Wrap::Mirror => {
+            let period = index.rem_euclid(2 * size);
+            if period < size {
+                period
+            } else {
+                2 * size - 1 - period
+            }
+        }
+    };
+
+    wrapped as usize
+}
+
+impl From<&Image> for Texture {
+    fn from(image: &Image) -> Self {
+        Texture::new(image.width, image.height, image.data.clone())
+    }
+}
+
+impl Texture {
+    /// Build a texture from rows of texels ordered top to bottom, generating its full mip chain.
+    pub fn new(width: usize, height: usize, texels: Vec<Pixel>) -> Self {
+        assert_eq!(texels.len(), width * height, "Texel count must match size");
+
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+        while levels.last().is_some_and(|l| l.width > 1 || l.height > 1) {
+            levels.push(levels.last().unwrap().downsample());
+        }
+
+        Self {
+            levels,
+            wrap: Wrap::Repeat,
+            filter: Filter::Bilinear,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Sample the full resolution level.
+    pub fn sample(&self, uv: &Vec2d) -> Pixel {
+        self.sample_grad(uv, &[0., 0.], &[0., 0.])
+    }
+
+    /// Sample with the screen-space derivatives of `uv` selecting the mip level and footprint.
+    pub fn sample_grad(&self, uv: &Vec2d, duv_dx: &Vec2d, duv_dy: &Vec2d) -> Pixel {
+        let size: Vec2d = [self.width() as f64, self.height() as f64];
+        let footprint_x: f64 = length(&[duv_dx[0] * size[0], duv_dx[1] * size[1]]);
+        let footprint_y: f64 = length(&[duv_dy[0] * size[0], duv_dy[1] * size[1]]);
+
+        let colour: Vec4d = match self.filter {
+            Filter::Nearest => self.nearest(0, uv),
+            Filter::Bilinear => self.bilinear(0, uv),
+            Filter::Trilinear => self.trilinear(footprint_x.max(footprint_y).log2(), uv),
+            Filter::Anisotropic(max_samples) => {
+                let (major, minor, axis) = if footprint_x >= footprint_y {
+                    (footprint_x, footprint_y, duv_dx)
+                } else {
+                    (footprint_y, footprint_x, duv_dy)
+                };
+
+                let ratio: f64 = if minor > 0. {
+                    major / minor
+                } else {
+                    max_samples as f64
+                };
+                let samples = (ratio.ceil() as usize).clamp(1, max_samples.max(1));
+                let lod: f64 = (major / samples as f64).log2();
+
+                let total = (0..samples).fold([0.; 4], |acc, s| {
+                    let offset = (s as f64 + 0.5) / samples as f64 - 0.5;
+                    add(
+                        &acc,
+                        &self.trilinear(lod, &add(uv, &scalar_mul(axis, offset))),
+                    )
+                });
+                scalar_mul(&total, 1. / samples as f64)
+            }
+        };
+
+        colour.into()
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vec4d {
+        let level = &self.levels[level];
+        let x = wrap_index(x, level.width, self.wrap);
+        let y = wrap_index(y, level.height, self.wrap);
+
+        level.texels[x + level.width * y].into()
+    }
+
+    /// Texel-space coordinates of `uv`, with texel centres at half-integers.
+    fn texel_coordinates(&self, level: usize, uv: &Vec2d) -> Vec2d {
+        let level = &self.levels[level];
+        [
+            uv[0] * level.width as f64,
+            (1. - uv[1]) * level.height as f64,
+        ]
+    }
+
+    fn nearest(&self, level: usize, uv: &Vec2d) -> Vec4d {
+        let [x, y] = self.texel_coordinates(level, uv);
+        self.texel(level, x.floor() as i64, y.floor() as i64)
+    }
+
+    fn bilinear(&self, level: usize, uv: &Vec2d) -> Vec4d {
+        let [x, y] = self.texel_coordinates(level, uv);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+
+        weighted_sum(
+            &[
+                self.texel(level, x0, y0),
+                self.texel(level, x0 + 1, y0),
+                self.texel(level, x0, y0 + 1),
+                self.texel(level, x0 + 1, y0 + 1),
+            ],
+            &[
+                (1. - tx) * (1. - ty),
+                tx * (1. - ty),
+                (1. - tx) * ty,
+                tx * ty,
+            ],
+        )
+    }
+
+    fn trilinear(&self, lod: f64, uv: &Vec2d) -> Vec4d {
+        let lod: f64 = lod.clamp(0., (self.levels.len() - 1) as f64);
+        let (fine, coarse) = (lod.floor() as usize, lod.ceil() as usize);
+        let t: f64 = lod - lod.floor();
+
+        weighted_sum(
+            &[self.bilinear(fine, uv), self.bilinear(coarse, uv)],
+            &[1. - t, t],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(value: u8) -> Pixel {
+        Pixel {
+            red: value,
+            green: value,
+            blue: value,
+            alpha: 255,
+        }
+    }
+
+    /// 2x2 texture with black and white texels on the top row, and grey ones below.
+    fn checker() -> Texture {
+        Texture::new(2, 2, vec![grey(0), grey(255), grey(100), grey(100)])
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(wrap_index(-1, 4, Wrap::Repeat), 3);
+        assert_eq!(wrap_index(5, 4, Wrap::Repeat), 1);
+        assert_eq!(wrap_index(-1, 4, Wrap::Clamp), 0);
+        assert_eq!(wrap_index(7, 4, Wrap::Clamp), 3);
+        assert_eq!(wrap_index(-1, 4, Wrap::Mirror), 0);
+        assert_eq!(wrap_index(4, 4, Wrap::Mirror), 3);
+        assert_eq!(wrap_index(9, 4, Wrap::Mirror), 1);
+    }
+
+    #[test]
+    fn nearest_sampling() {
+        let mut texture = checker();
+        texture.filter = Filter::Nearest;
+
+        assert_eq!(texture.sample(&[0.25, 0.75]), grey(0));
+        assert_eq!(texture.sample(&[0.75, 0.75]), grey(255));
+        assert_eq!(texture.sample(&[0.75, 0.25]), grey(100));
+        assert_eq!(texture.sample(&[1.25, 0.75]), grey(0));
+    }
+
+    #[test]
+    fn bilinear_sampling() {
+        let mut texture = checker();
+        texture.wrap = Wrap::Clamp;
+
+        assert_eq!(texture.sample(&[0.25, 0.75]), grey(0));
+        assert_eq!(texture.sample(&[0.5, 0.75]), grey(128));
+        assert_eq!(texture.sample(&[0.25, 0.5]), grey(50));
+    }
+
+    #[test]
+    fn alpha_is_filtered() {
+        let clear = Pixel {
+            alpha: 0,
+            ..grey(255)
+        };
+        let texture = Texture::new(2, 1, vec![clear, grey(255)]);
+
+        assert_eq!(texture.sample(&[0.5, 0.5]).alpha, 128);
+        assert_eq!(texture.levels[1].texels[0].alpha, 128);
+    }
+
+    #[test]
+    fn mip_chain() {
+        let texture = Texture::new(5, 3, vec![grey(40); 15]);
+        let sizes: Vec<(usize, usize)> =
+            texture.levels.iter().map(|l| (l.width, l.height)).collect();
+
+        assert_eq!(sizes, vec![(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(checker().levels[1].texels, vec![grey(114)]);
+    }
+
+    #[test]
+    fn odd_sizes_keep_every_texel() {
+        // Only the last column is white, which a 2x2 box filter would drop
+        let texels: Vec<Pixel> = (0..15)
+            .map(|n| grey(if n % 5 == 4 { 255 } else { 0 }))
+            .collect();
+        let texture = Texture::new(5, 3, texels);
+
+        // The right texel of the 2x1 level covers two fifths of the last column
+        assert_eq!(texture.levels[1].texels, vec![grey(0), grey(102)]);
+        // Whose mean, a fifth of white, is kept down to the last level
+        assert_eq!(texture.levels[2].texels, vec![grey(51)]);
+    }
+
+    #[test]
+    fn trilinear_sampling_uses_footprint() {
+        let mut texture = checker();
+        texture.filter = Filter::Trilinear;
+
+        assert_eq!(
+            texture.sample_grad(&[0.25, 0.75], &[0.5, 0.], &[0., 0.5]),
+            grey(0)
+        );
+        assert_eq!(
+            texture.sample_grad(&[0.25, 0.75], &[1., 0.], &[0., 1.]),
+            grey(114)
+        );
+        assert_eq!(
+            texture.sample_grad(&[0.25, 0.75], &[4., 0.], &[0., 4.]),
+            grey(114)
+        );
+    }
+
+    #[test]
+    fn anisotropic_sampling_follows_major_axis() {
+        let mut texture = Texture::new(4, 1, vec![grey(0), grey(0), grey(200), grey(200)]);
+        texture.filter = Filter::Anisotropic(4);
+
+        assert_eq!(
+            texture.sample_grad(&[0.5, 0.5], &[1., 0.], &[0., 0.25]),
+            grey(100)
+        );
+        assert_eq!(
+            texture.sample_grad(&[0.125, 0.5], &[0., 0.], &[0., 0.25]),
+            grey(0)
+        );
+    }
+}
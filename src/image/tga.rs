@@ -0,0 +1,292 @@
+use std::io::{Error, ErrorKind};
+
+use crate::image::pixel::Pixel;
+use crate::image::{Channels, Image};
+
+const HEADER_SIZE: usize = 18;
+const TRUECOLOUR: u8 = 2;
+const GREYSCALE: u8 = 3;
+/// Added to an image type when its pixels are run-length encoded.
+const RLE: u8 = 8;
+/// Descriptor bits holding the number of alpha bits per pixel.
+const ALPHA_BITS: u8 = 0x0f;
+/// Descriptor bits for pixels stored right to left and rows stored top to bottom.
+const RIGHT_TO_LEFT: u8 = 0x10;
+const TOP_TO_BOTTOM: u8 = 0x20;
+/// Longest run or literal sequence a single packet can hold.
+const MAX_PACKET: usize = 128;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Pixels of an RLE stream, where each packet is a run of one repeated pixel or a sequence of
+/// literal ones, and packets may cross rows.
+fn decode_rle(data: &[u8], count: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, Error> {
+    // A packet expands to at most `MAX_PACKET` pixels, so the header cannot claim more than the
+    // data can hold into a huge allocation
+    let mut pixels: Vec<u8> =
+        Vec::with_capacity((count * bytes_per_pixel).min(data.len() * MAX_PACKET));
+    let mut offset: usize = 0;
+
+    while pixels.len() < count * bytes_per_pixel {
+        let packet: u8 = *data
+            .get(offset)
+            .ok_or_else(|| invalid("Truncated RLE data"))?;
+        let length: usize = (packet & 0x7f) as usize + 1;
+        offset += 1;
+
+        if packet & 0x80 != 0 {
+            let pixel: &[u8] = data
+                .get(offset..offset + bytes_per_pixel)
+                .ok_or_else(|| invalid("Truncated RLE data"))?;
+            (0..length).for_each(|_| pixels.extend_from_slice(pixel));
+            offset += bytes_per_pixel;
+        } else {
+            let literal: &[u8] = data
+                .get(offset..offset + length * bytes_per_pixel)
+                .ok_or_else(|| invalid("Truncated RLE data"))?;
+            pixels.extend_from_slice(literal);
+            offset += length * bytes_per_pixel;
+        }
+    }
+
+    pixels.truncate(count * bytes_per_pixel);
+    Ok(pixels)
+}
+
+/// RLE packets for one row, runs being used wherever at least two pixels repeat.
+fn encode_rle(row: &[u8], bytes_per_pixel: usize, rle: &mut Vec<u8>) {
+    let row: Vec<&[u8]> = row.chunks(bytes_per_pixel).collect();
+    let mut x: usize = 0;
+
+    while x < row.len() {
+        let run: usize = row[x..]
+            .iter()
+            .take(MAX_PACKET)
+            .take_while(|&&p| p == row[x])
+            .count();
+
+        if run > 1 {
+            rle.push(0x80 | (run - 1) as u8);
+            rle.extend_from_slice(row[x]);
+            x += run;
+        } else {
+            let start: usize = x;
+            while x < row.len()
+                && x - start < MAX_PACKET
+                && (x + 1 == row.len() || row[x] != row[x + 1])
+            {
+                x += 1;
+            }
+            rle.push((x - start - 1) as u8);
+            row[start..x].iter().for_each(|p| rle.extend_from_slice(p));
+        }
+    }
+}
+
+impl Image {
+    /// RLE-compressed 24-bit TGA, or 32-bit if the image keeps alpha, with rows stored from the
+    /// top down. Panics if either side is longer than the 65535 pixels the format can store.
+    pub fn tga(&self) -> Vec<u8> {
+        let [width, height]: [u16; 2] = [self.width, self.height].map(|size| {
+            u16::try_from(size).expect("TGA images are at most 65535 pixels on a side")
+        });
+        let (depth, bytes_per_pixel, alpha_bits): (u8, usize, u8) = match self.channels {
+            Channels::Rgb => (24, 3, 0),
+            Channels::Rgba => (32, 4, 8),
+        };
+
+        let mut tga: Vec<u8> = vec![0, 0, TRUECOLOUR + RLE, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        tga.extend_from_slice(&width.to_le_bytes());
+        tga.extend_from_slice(&height.to_le_bytes());
+        tga.extend_from_slice(&[depth, TOP_TO_BOTTOM | alpha_bits]);
+
+        for row in self.data.chunks(self.width.max(1)) {
+            let bgra: Vec<u8> = row
+                .iter()
+                .flat_map(|p| {
+                    [p.blue, p.green, p.red, p.alpha]
+                        .into_iter()
+                        .take(bytes_per_pixel)
+                })
+                .collect();
+            encode_rle(&bgra, bytes_per_pixel, &mut tga);
+        }
+
+        tga
+    }
+
+    /// Decode an uncompressed or RLE-compressed TGA holding 24 or 32-bit truecolour or 8-bit
+    /// greyscale pixels, in any of the four orientations. Alpha is kept if the descriptor says the
+    /// pixels have 8 bits of it.
+    pub fn decode_tga(bytes: &[u8]) -> Result<Self, Error> {
+        let header: &[u8] = bytes
+            .get(..HEADER_SIZE)
+            .ok_or_else(|| invalid("Truncated TGA header"))?;
+        let id_length: usize = header[0] as usize;
+        let colour_map_type: u8 = header[1];
+        let image_type: u8 = header[2];
+        let width: usize = u16::from_le_bytes([header[12], header[13]]) as usize;
+        let height: usize = u16::from_le_bytes([header[14], header[15]]) as usize;
+        let pixel_depth: u8 = header[16];
+        let descriptor: u8 = header[17];
+
+        if colour_map_type != 0 {
+            return Err(invalid("Colour-mapped TGA files are not supported"));
+        }
+        let bytes_per_pixel: usize = match (image_type & !RLE, pixel_depth) {
+            (TRUECOLOUR, 24) => 3,
+            (TRUECOLOUR, 32) => 4,
+            (GREYSCALE, 8) => 1,
+            _ => return Err(invalid("Unsupported TGA image type or pixel depth")),
+        };
+
+        let data: &[u8] = bytes
+            .get(HEADER_SIZE + id_length..)
+            .ok_or_else(|| invalid("Truncated TGA image ID"))?;
+        let count: usize = width * height;
+        let pixels: Vec<u8> = if image_type & RLE != 0 {
+            decode_rle(data, count, bytes_per_pixel)?
+        } else {
+            data.get(..count * bytes_per_pixel)
+                .ok_or_else(|| invalid("Not enough TGA image data"))?
+                .to_vec()
+        };
+
+        let alpha_channel: bool = bytes_per_pixel == 4 && descriptor & ALPHA_BITS == 8;
+        let mut image = Image::blank(width, height);
+        if alpha_channel {
+            image.channels = Channels::Rgba;
+        }
+
+        for (index, texel) in pixels.chunks_exact(bytes_per_pixel).enumerate() {
+            let (mut x, mut y) = (index % width, index / width);
+            if descriptor & RIGHT_TO_LEFT != 0 {
+                x = width - x - 1;
+            }
+            if descriptor & TOP_TO_BOTTOM == 0 {
+                y = height - y - 1;
+            }
+
+            image.data[x + width * y] = match texel {
+                [grey] => Pixel {
+                    red: *grey,
+                    green: *grey,
+                    blue: *grey,
+                    alpha: 255,
+                },
+                [blue, green, red, rest @ ..] => Pixel {
+                    red: *red,
+                    green: *green,
+                    blue: *blue,
+                    alpha: match rest {
+                        [alpha] if alpha_channel => *alpha,
+                        _ => 255,
+                    },
+                },
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::pixel::{BLUE, GREEN, RED, WHITE};
+
+    fn header(image_type: u8, width: u16, height: u16, depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header.extend_from_slice(&width.to_le_bytes());
+        header.extend_from_slice(&height.to_le_bytes());
+        header.extend_from_slice(&[depth, descriptor]);
+        header
+    }
+
+    #[test]
+    fn round_trip() {
+        let (width, height) = (300, 4);
+
+        for channels in [Channels::Rgb, Channels::Rgba] {
+            let mut image = Image::blank(width, height);
+            image.set_channels(channels);
+            for (index, pixel) in image.data.iter_mut().enumerate() {
+                // Long runs, short runs and literals
+                *pixel = match index % width {
+                    0..150 => RED,
+                    150..152 => GREEN,
+                    x => Pixel {
+                        red: x as u8,
+                        green: index as u8,
+                        blue: 0,
+                        alpha: if channels == Channels::Rgba { 100 } else { 255 },
+                    },
+                };
+            }
+
+            let tga: Vec<u8> = image.tga();
+            assert!(tga.len() < 3 * width * height);
+
+            let decoded = Image::decode_tga(&tga).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, height));
+            assert_eq!(decoded.channels, channels);
+            assert_eq!(decoded.data, image.data);
+        }
+    }
+
+    #[test]
+    fn bottom_up_rows_are_flipped() {
+        let mut tga: Vec<u8> = header(GREYSCALE, 1, 2, 8, 0);
+        tga.extend_from_slice(&[0, 255]);
+
+        let black = Pixel {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 255,
+        };
+        let image = Image::decode_tga(&tga).unwrap();
+        assert_eq!(image.data, [WHITE, black]);
+    }
+
+    #[test]
+    fn decode_rle_with_alpha_and_right_to_left() {
+        // A run of two blue pixels then a literal red one, stored from the right
+        let mut tga: Vec<u8> = header(
+            TRUECOLOUR + RLE,
+            3,
+            1,
+            32,
+            TOP_TO_BOTTOM | RIGHT_TO_LEFT | 8,
+        );
+        tga.extend_from_slice(&[0x81, 255, 0, 0, 128, 0x00, 0, 0, 255, 128]);
+
+        let image = Image::decode_tga(&tga).unwrap();
+        assert_eq!(image.channels, Channels::Rgba);
+        assert_eq!(
+            image.data,
+            [RED, BLUE, BLUE].map(|p| Pixel { alpha: 128, ..p })
+        );
+    }
+
+    #[test]
+    fn unsupported_and_truncated_files_are_rejected() {
+        assert!(Image::decode_tga(&header(1, 1, 1, 8, 0)).is_err());
+        assert!(Image::decode_tga(&header(TRUECOLOUR, 2, 2, 24, 0)).is_err());
+        assert!(Image::decode_tga(&header(TRUECOLOUR + RLE, 2, 2, 24, 0)).is_err());
+
+        // Claims far more pixels than its single packet holds, without reserving room for them
+        let mut huge: Vec<u8> = header(TRUECOLOUR + RLE, 65535, 65535, 32, 8);
+        huge.push(0x80);
+        assert!(Image::decode_tga(&huge).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 65535 pixels")]
+    fn oversized_images_cannot_be_encoded() {
+        Image::blank(65536, 1).tga();
+    }
+}
@@ -0,0 +1,280 @@
+use std::convert::identity;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::geometry::Vertex;
+use crate::image::Image;
+use crate::image::picking::PrimitiveId;
+use crate::image::pixel::Pixel;
+use crate::image::raster::Setup;
+use crate::math::vector::Vec3d;
+
+/// Side of the square tiles the image is split into for parallel rendering, in pixels.
+pub const TILE_SIZE: usize = 64;
+
+/// Rectangle of pixels, addressed by the column and the top-down row of its top left pixel.
+struct Tile {
+    x: usize,
+    row: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Values of the pixels of `tile` in a buffer `width` pixels wide, `count` values per pixel.
+fn extract<T: Copy>(values: &[T], width: usize, count: usize, tile: &Tile) -> Vec<T> {
+    (tile.row..tile.row + tile.height)
+        .flat_map(|row| {
+            let start: usize = (tile.x + width * row) * count;
+            values[start..start + tile.width * count].iter().copied()
+        })
+        .collect()
+}
+
+/// Inverse of `extract`, copying the values of a tile back into the whole buffer.
+fn insert<T: Copy>(values: &mut [T], width: usize, count: usize, tile: &Tile, tiled: &[T]) {
+    for (row, line) in tiled.chunks(tile.width * count).enumerate() {
+        let start: usize = (tile.x + width * (tile.row + row)) * count;
+        values[start..start + line.len()].copy_from_slice(line);
+    }
+}
+
+impl Image {
+    /// Copy of the pixels, depths, samples and picking records of `tile`, as an image of its own.
+    fn tile(&self, tile: &Tile) -> Image {
+        let mut image = Image::blank(tile.width, tile.height);
+        image.origin = self.origin;
+        image.channels = self.channels;
+        image.blend = self.blend;
+        image.data = extract(&self.data, self.width, 1, tile);
+        image.zbuffer = self.zbuffer.resized(tile.width, tile.height);
+        image.zbuffer.values_mut().copy_from_slice(&extract(
+            self.zbuffer.values(),
+            self.width,
+            1,
+            tile,
+        ));
+        image.hits = self
+            .hits
+            .as_ref()
+            .map(|hits| extract(hits, self.width, 1, tile));
+
+        if let Some(multisample) = &self.multisample {
+            image.set_samples(self.samples());
+            let (colour, depth) = multisample.buffers();
+            let (tile_colour, tile_depth) = image.multisample.as_mut().unwrap().buffers_mut();
+            let count: usize = self.samples().count();
+            tile_colour.copy_from_slice(&extract(colour, self.width, count, tile));
+            tile_depth.copy_from_slice(&extract(depth, self.width, count, tile));
+        }
+
+        image
+    }
+
+    /// Copy a tile rendered from `tile` back into the image.
+    fn untile(&mut self, tile: &Tile, image: &Image) {
+        insert(&mut self.data, self.width, 1, tile, &image.data);
+        insert(
+            self.zbuffer.values_mut(),
+            self.width,
+            1,
+            tile,
+            image.zbuffer.values(),
+        );
+        self.hiz.invalidate();
+        if let (Some(hits), Some(tiled)) = (&mut self.hits, &image.hits) {
+            insert(hits, self.width, 1, tile, tiled);
+        }
+
+        let count: usize = self.samples().count();
+        if let (Some(multisample), Some(tiled)) = (&mut self.multisample, &image.multisample) {
+            let (colour, depth) = multisample.buffers_mut();
+            let (tile_colour, tile_depth) = tiled.buffers();
+            insert(colour, self.width, count, tile, tile_colour);
+            insert(depth, self.width, count, tile, tile_depth);
+        }
+    }
+
+    /// Render `triangles` in order, as `shaded_triangle` would with `fragment` called with each
+    /// triangle's index and barycentric weights. The image is split into tiles, each triangle is
+    /// binned into the tiles it overlaps, and tiles are rasterised in parallel on up to `threads`
+    /// threads, which draws the same pixels as rendering serially. If picking is enabled, hits
+    /// record the mesh of the current primitive and the triangle's index. The HDR target and
+    /// attachments are not used.
+    pub fn tiled_triangles<F>(&mut self, triangles: &[[Vertex; 3]], threads: usize, fragment: F)
+    where
+        F: Fn(usize, Vec3d) -> Pixel + Sync,
+    {
+        let columns: usize = self.width.div_ceil(TILE_SIZE);
+        let tiles: Vec<Tile> = (0..self.height.div_ceil(TILE_SIZE))
+            .flat_map(|r| (0..columns).map(move |c| (c * TILE_SIZE, r * TILE_SIZE)))
+            .map(|(x, row)| Tile {
+                x,
+                row,
+                width: TILE_SIZE.min(self.width - x),
+                height: TILE_SIZE.min(self.height - row),
+            })
+            .collect();
+
+        // Triangles are binned in order, so every tile draws its triangles in their given order
+        let setups: Vec<Option<Setup>> = triangles
+            .iter()
+            .map(|[i, j, k]| self.setup(i, j, k))
+            .collect();
+        let mut bins: Vec<Vec<usize>> = vec![Vec::new(); tiles.len()];
+        for (n, setup) in setups.iter().enumerate() {
+            let Some([[left, right], [bottom, top]]) = setup
+                .as_ref()
+                .and_then(|setup| setup.bounds(self.width, self.height))
+            else {
+                continue;
+            };
+
+            let rows = (self.height - top - 1) / TILE_SIZE..=(self.height - bottom - 1) / TILE_SIZE;
+            for row in rows {
+                for column in left / TILE_SIZE..=right / TILE_SIZE {
+                    bins[column + columns * row].push(n);
+                }
+            }
+        }
+
+        let next = AtomicUsize::new(0);
+        let image: &Image = self;
+        let rendered: Vec<(usize, Image)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.max(1))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut rendered: Vec<(usize, Image)> = Vec::new();
+                        loop {
+                            let t: usize = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(tile) = tiles.get(t) else {
+                                return rendered;
+                            };
+                            if bins[t].is_empty() {
+                                continue;
+                            }
+
+                            // Rasterisation measures rows from the bottom of the image
+                            let bottom: usize = image.height - tile.row - tile.height;
+                            let mut tiled: Image = image.tile(tile);
+                            for &n in &bins[t] {
+                                let setup: Setup =
+                                    setups[n].as_ref().unwrap().translated(tile.x, bottom);
+                                tiled.primitive =
+                                    image.primitive.map(|id| PrimitiveId { triangle: n, ..id });
+                                tiled.shade(&setup, identity, |_, _, barycentric| {
+                                    fragment(n, barycentric)
+                                });
+                            }
+                            rendered.push((t, tiled));
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Rendering thread panicked"))
+                .collect()
+        });
+
+        for (t, tiled) in rendered {
+            self.untile(&tiles[t], &tiled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::antialias::Samples;
+    use crate::image::blend::Blend;
+
+    /// Overlapping triangles at various depths and with various alphas, some crossing tiles.
+    fn scene() -> Vec<[Vertex; 3]> {
+        (0..40)
+            .map(|n| {
+                let t: f64 = n as f64 * 0.37;
+                let (x, y, z) = (t.sin() * 0.8, (t * 1.7).cos() * 0.8, (t * 2.3).sin() * 0.9);
+                let size: f64 = 0.1 + (n % 5) as f64 * 0.15;
+                [
+                    [x, y],
+                    [x + size, y + size * 0.3],
+                    [x - size * 0.2, y + size],
+                ]
+                .map(|[x, y]| Vertex::from([x, y, z]))
+            })
+            .collect()
+    }
+
+    fn colour(n: usize, barycentric: Vec3d) -> Pixel {
+        Pixel {
+            red: (barycentric[0] * 255.) as u8,
+            green: (n * 6) as u8,
+            blue: (barycentric[2] * 255.) as u8,
+            alpha: 100 + (n * 3) as u8,
+        }
+    }
+
+    #[test]
+    fn tiled_rendering_matches_serial_rendering() {
+        let triangles: Vec<[Vertex; 3]> = scene();
+
+        for samples in [Samples::One, Samples::Four] {
+            let blank = || {
+                let mut image = Image::blank(150, 130);
+                image.set_blend(Blend::AlphaOver);
+                image.set_samples(samples);
+                image
+            };
+
+            let mut serial: Image = blank();
+            for (n, [i, j, k]) in triangles.iter().enumerate() {
+                serial.shaded_triangle(i, j, k, |barycentric| colour(n, barycentric));
+            }
+            serial.resolve();
+
+            let mut tiled: Image = blank();
+            tiled.tiled_triangles(&triangles, 4, colour);
+            tiled.resolve();
+
+            assert!(serial.data == tiled.data, "{samples:?}");
+            assert_eq!(serial.zbuffer.values(), tiled.zbuffer.values());
+        }
+    }
+
+    #[test]
+    fn tiled_rendering_records_hits() {
+        let triangles: Vec<[Vertex; 3]> = scene();
+        let blank = || {
+            let mut image = Image::blank(150, 130);
+            image.enable_picking();
+            image.set_primitive(Some(PrimitiveId {
+                mesh: 3,
+                triangle: 0,
+            }));
+            image
+        };
+
+        let mut serial: Image = blank();
+        for (n, [i, j, k]) in triangles.iter().enumerate() {
+            serial.set_primitive(Some(PrimitiveId {
+                mesh: 3,
+                triangle: n,
+            }));
+            serial.shaded_triangle(i, j, k, |barycentric| colour(n, barycentric));
+        }
+
+        let mut tiled: Image = blank();
+        tiled.tiled_triangles(&triangles, 4, colour);
+
+        let mut picked: usize = 0;
+        for y in 0..130 {
+            for x in 0..150 {
+                assert_eq!(serial.pick(x, y), tiled.pick(x, y), "{x}, {y}");
+                picked += tiled.pick(x, y).is_some() as usize;
+            }
+        }
+        assert!(picked > 1000);
+        assert!(tiled.pick(75, 65).is_some_and(|hit| hit.id.mesh == 3));
+    }
+}
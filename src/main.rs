@@ -1,44 +1,138 @@
+pub mod draw;
 pub mod geometry;
 pub mod image;
 pub mod math;
+pub mod shading;
+pub mod shadow;
+pub mod wireframe;
 
-use crate::geometry::Geometry;
+use crate::geometry::{Geometry, Vertex};
+use crate::image::antialias::Samples;
+use crate::image::blend::Blend;
+use crate::image::texture::Texture;
+use crate::math::matrix::Matrix4d;
+use crate::math::transform::look_at;
+use crate::math::vector::{Vec2d, Vec3d, cross_product, sub, weighted_sum};
+use crate::shading::{Fragment, Material, NormalMap, tangent_frame};
+use crate::shadow::ShadowMap;
+use crate::wireframe::{Mode, Wireframe, render};
 use image::Image;
-use image::pixel::Pixel;
 
 const IMAGE_WIDTH: usize = 800;
-const IMAGE_HEIGHT: usize = IMAGE_WIDTH;
+const IMAGE_HEIGHT: usize = 800;
+/// Coverage samples per pixel, for multisample anti-aliasing.
+const SAMPLES: Samples = Samples::Four;
+const RENDER_MODE: Mode = Mode::Filled;
 const OBJ_FILE_PATH: &str = "obj/african_head/african_head.obj";
+const DIFFUSE_FILE_PATH: &str = "obj/african_head/african_head_diffuse.tga";
+const NORMAL_FILE_PATH: &str = "obj/african_head/african_head_nm_tangent.tga";
+const SPECULAR_FILE_PATH: &str = "obj/african_head/african_head_spec.tga";
+const LIGHT_DIRECTION: Vec3d = [1., 1., 1.];
+const VIEW_DIRECTION: Vec3d = [0., 0., 1.];
 
-fn rand() -> u8 {
-    let mut now = std::time::Instant::now().elapsed().as_nanos();
-    now ^= now << 13;
-    now ^= now >> 17;
-    now ^= now << 5;
+/// Attributes of a face that its fragments interpolate, gathered before rendering.
+struct FaceAttributes {
+    corners: [Vec3d; 3],
+    normals: [Vec3d; 3],
+    uvs: [Vec2d; 3],
+    duv: [Vec2d; 2],
+    tangent: Vec3d,
+    bitangent: Vec3d,
+    front_facing: bool,
+}
 
-    now as u8
+/// Texture stored as a TGA at `path`, or `None` if there is no such file.
+fn load_texture(path: &str) -> Result<Option<Texture>, std::io::Error> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(Some(Texture::from(&Image::decode_tga(&bytes)?))),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
 }
 
 fn main() -> Result<(), std::io::Error> {
     let input_string = std::fs::read_to_string(OBJ_FILE_PATH).unwrap_or_default();
     let geometry = Geometry::decode_obj(input_string.as_str());
 
+    let light: Matrix4d = look_at(&LIGHT_DIRECTION, &[0., 0., 0.], &[0., 1., 0.]);
+    let shadow = ShadowMap::render(&geometry, light, IMAGE_WIDTH, IMAGE_HEIGHT);
+
+    let material = Material {
+        diffuse: load_texture(DIFFUSE_FILE_PATH)?,
+        normal: load_texture(NORMAL_FILE_PATH)?.map(NormalMap::Tangent),
+        specular: load_texture(SPECULAR_FILE_PATH)?,
+        ..Default::default()
+    };
     let mut img: Image = Image::blank(IMAGE_WIDTH, IMAGE_HEIGHT);
+    img.set_samples(SAMPLES);
 
-    for face in geometry.faces {
-        let colour = Pixel {
-            red: rand(),
-            green: rand(),
-            blue: rand(),
-        };
-
-        img.triangle(
-            colour,
-            &geometry.vertices[face.0],
-            &geometry.vertices[face.1],
-            &geometry.vertices[face.2],
-        );
-    }
+    // Faces are drawn from the back so that any translucent ones blend over what is behind them
+    img.set_blend(Blend::AlphaOver);
+    let wireframe = Wireframe::default();
+    let threads: usize = std::thread::available_parallelism().map_or(1, |n| n.get());
+    render(
+        &mut img,
+        &geometry,
+        RENDER_MODE,
+        &wireframe,
+        |img, order| {
+            let mut triangles: Vec<[Vertex; 3]> = Vec::with_capacity(order.len());
+            let mut attributes: Vec<FaceAttributes> = Vec::with_capacity(order.len());
+
+            for &f in order {
+                let face = &geometry.faces[f];
+                let (i, j, k) = (
+                    &geometry.vertices[face.0],
+                    &geometry.vertices[face.1],
+                    &geometry.vertices[face.2],
+                );
+                let corners: [Vec3d; 3] = [(*i).into(), (*j).into(), (*k).into()];
+
+                let face_normal: Vec3d = cross_product(
+                    &sub(&corners[1], &corners[0]),
+                    &sub(&corners[2], &corners[0]),
+                );
+                let normals: [Vec3d; 3] = match geometry.normal_faces.get(f) {
+                    Some(n) => [n.0, n.1, n.2].map(|index| geometry.normals[index]),
+                    None => [face_normal; 3],
+                };
+                let uvs: [Vec2d; 3] = match geometry.uv_faces.get(f) {
+                    Some(t) => [t.0, t.1, t.2].map(|index| geometry.uvs[index]),
+                    None => [[0.; 2]; 3],
+                };
+
+                let [tangent, bitangent] = tangent_frame(&corners, &uvs);
+                let [dx, dy] = img.barycentric_derivatives(i, j, k);
+
+                triangles.push([*i, *j, *k]);
+                attributes.push(FaceAttributes {
+                    corners,
+                    normals,
+                    uvs,
+                    duv: [weighted_sum(&uvs, &dx), weighted_sum(&uvs, &dy)],
+                    tangent,
+                    bitangent,
+                    front_facing: img.front_facing(i, j, k),
+                });
+            }
+
+            img.tiled_triangles(&triangles, threads, |n, barycentric| {
+                let face: &FaceAttributes = &attributes[n];
+                let fragment = Fragment {
+                    position: weighted_sum(&face.corners, &barycentric),
+                    normal: weighted_sum(&face.normals, &barycentric),
+                    uv: weighted_sum(&face.uvs, &barycentric),
+                    duv: face.duv,
+                    tangent: face.tangent,
+                    bitangent: face.bitangent,
+                    front_facing: face.front_facing,
+                };
+                let visibility: f64 = shadow.visibility(&fragment.position.into());
+
+                material.shade(&fragment, &LIGHT_DIRECTION, &VIEW_DIRECTION, visibility)
+            });
+        },
+    );
 
-    std::fs::write("output.ppm", img.ppm())
+    std::fs::write("output.png", img.png())
 }
@@ -1,2 +1,3 @@
 pub mod matrix;
+pub mod transform;
 pub mod vector;
@@ -41,6 +41,12 @@ pub fn mul<const N: usize>(lhs: &MatrixNd<N>, rhs: &MatrixNd<N>) -> MatrixNd<N>
     result
 }
 
+pub fn mul_vector<const N: usize>(matrix: &MatrixNd<N>, vector: &[f64; N]) -> [f64; N] {
+    let mut result = [0.; N];
+    (0..N).for_each(|i| result[i] = dot_product(&matrix[i], vector));
+    result
+}
+
 #[allow(clippy::needless_range_loop)]
 pub fn transpose<const N: usize>(matrix: &MatrixNd<N>) -> MatrixNd<N> {
     let mut result = [[0.; N]; N];
@@ -85,7 +91,12 @@ fn cofactor(matrix: &[Vec<f64>], row: usize, column: usize) -> f64 {
         }
     }
 
-    det(&submatrix) * (if (row + column) % 2 == 0 { 1. } else { -1. })
+    let sign: f64 = if (row + column).is_multiple_of(2) {
+        1.
+    } else {
+        -1.
+    };
+    det(&submatrix) * sign
 }
 
 fn div<const N: usize>(matrix: &MatrixNd<N>, factor: f64) -> MatrixNd<N> {
@@ -153,6 +164,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_mul_vector() {
+        let matrix: Matrix3d = [[1., 2., 3.], [4., 5., 6.], [7., 8., 9.]];
+        assert_eq!(mul_vector(&matrix, &[1., 0., -1.]), [-2., -2., -2.]);
+    }
+
     #[test]
     fn test_transpose() {
         {
@@ -0,0 +1,67 @@
+use crate::math::matrix::{Matrix4d, mul_vector};
+use crate::math::vector::{Vec3d, cross_product, dot_product, sub, unit};
+
+/// View matrix looking from `eye` towards `centre`, keeping `centre` at the origin of view space.
+pub fn look_at(eye: &Vec3d, centre: &Vec3d, up: &Vec3d) -> Matrix4d {
+    let z: Vec3d = unit(&sub(eye, centre));
+    let x: Vec3d = unit(&cross_product(up, &z));
+    let y: Vec3d = unit(&cross_product(&z, &x));
+
+    [
+        [x[0], x[1], x[2], -dot_product(&x, centre)],
+        [y[0], y[1], y[2], -dot_product(&y, centre)],
+        [z[0], z[1], z[2], -dot_product(&z, centre)],
+        [0., 0., 0., 1.],
+    ]
+}
+
+/// Projection matrix of a camera at the origin looking down `-z`, with a vertical field of view
+/// of `fov_y` radians. Points between the `near` and `far` planes are mapped into clip space, and
+/// after dividing by `w` their `z` runs from 1 at the near plane to -1 at the far plane.
+pub fn perspective(fov_y: f64, aspect: f64, near: f64, far: f64) -> Matrix4d {
+    let focal: f64 = 1. / (fov_y / 2.).tan();
+
+    [
+        [focal / aspect, 0., 0., 0.],
+        [0., focal, 0., 0.],
+        [
+            0.,
+            0.,
+            (far + near) / (far - near),
+            2. * far * near / (far - near),
+        ],
+        [0., 0., -1., 0.],
+    ]
+}
+
+/// Transform a point in homogeneous coordinates, and divide back into 3D.
+pub fn apply(matrix: &Matrix4d, point: &Vec3d) -> Vec3d {
+    let [x, y, z, w] = mul_vector(matrix, &[point[0], point[1], point[2], 1.]);
+    [x / w, y / w, z / w]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_look_at() {
+        let view: Matrix4d = look_at(&[0., 0., 3.], &[0., 0., 0.], &[0., 1., 0.]);
+        assert_eq!(apply(&view, &[1., 2., 3.]), [1., 2., 3.]);
+
+        let view: Matrix4d = look_at(&[1., 0., 0.], &[0., 0., 0.], &[0., 1., 0.]);
+        assert_eq!(apply(&view, &[1., 0., 0.]), [0., 0., 1.]);
+        assert_eq!(apply(&view, &[0., 0., 1.]), [-1., 0., 0.]);
+    }
+
+    #[test]
+    fn test_perspective() {
+        let projection: Matrix4d = perspective(std::f64::consts::FRAC_PI_2, 2., 1., 3.);
+
+        let close = |a: Vec3d, b: Vec3d| (0..3).all(|n| (a[n] - b[n]).abs() < 1e-12);
+
+        // Corners of the near and far planes
+        assert!(close(apply(&projection, &[2., 1., -1.]), [1., 1., 1.]));
+        assert!(close(apply(&projection, &[-6., -3., -3.]), [-1., -1., -1.]));
+    }
+}
@@ -38,6 +38,15 @@ pub fn div<const N: usize>(vector: &VecNd<N>, divisor: f64) -> VecNd<N> {
     scalar_mul(vector, 1.0 / divisor)
 }
 
+pub fn weighted_sum<const N: usize, const M: usize>(
+    vectors: &[VecNd<N>; M],
+    weights: &VecNd<M>,
+) -> VecNd<N> {
+    let mut result = [0.; N];
+    (0..M).for_each(|i| result = add(&result, &scalar_mul(&vectors[i], weights[i])));
+    result
+}
+
 pub fn length<const N: usize>(vector: &VecNd<N>) -> f64 {
     let mut length_squared: f64 = 0.;
     (0..N).for_each(|i| length_squared += vector[i] * vector[i]);
@@ -73,6 +82,12 @@ mod tests {
         assert_eq!(sub(&lhs, &rhs), dist);
     }
 
+    #[test]
+    fn test_weighted_sum() {
+        let vectors: [Vec2d; 3] = [[1., 0.], [0., 2.], [4., 4.]];
+        assert_eq!(weighted_sum(&vectors, &[0.5, 0.25, 0.25]), [1.5, 1.5]);
+    }
+
     #[test]
     fn test_length() {
         let vector: Vec4d = [-1., -1., -3., 6.];
@@ -0,0 +1,411 @@
+pub mod pbr;
+
+use crate::image::pixel::{Pixel, srgb_to_linear};
+use crate::image::texture::Texture;
+use crate::math::vector::{
+    Vec2d, Vec3d, Vec4d, add, cross_product, dot_product, length, mul, scalar_mul, sub, unit,
+    weighted_sum,
+};
+
+pub enum NormalMap {
+    /// Normals relative to the surface's tangent frame, with blue pointing away from the surface.
+    Tangent(Texture),
+    /// Normals in the coordinates of the model itself.
+    Object(Texture),
+}
+
+/// Surface attributes interpolated at a single fragment.
+#[derive(Copy, Clone, Debug)]
+pub struct Fragment {
+    pub position: Vec3d,
+    pub normal: Vec3d,
+    pub uv: Vec2d,
+    /// Screen-space derivatives of `uv` along the image's x and y axes.
+    pub duv: [Vec2d; 2],
+    /// Directions of increasing `u` and `v` across the surface.
+    pub tangent: Vec3d,
+    pub bitangent: Vec3d,
+    /// Whether the fragment belongs to a triangle facing the viewer.
+    pub front_facing: bool,
+}
+
+pub struct Material {
+    /// Colour used when there is no diffuse map.
+    pub albedo: Vec3d,
+    pub diffuse: Option<Texture>,
+    pub normal: Option<NormalMap>,
+    /// Phong exponent, taken from the red channel.
+    pub specular: Option<Texture>,
+    pub emissive: Option<Texture>,
+    pub ambient: f64,
+    pub specular_strength: f64,
+    /// Scales the alpha of the diffuse map, or of the albedo, which is opaque.
+    pub opacity: f64,
+    /// Light back faces as if they faced the viewer, by reversing their normals.
+    pub two_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: [255.; 3],
+            diffuse: None,
+            normal: None,
+            specular: None,
+            emissive: None,
+            ambient: 0.2,
+            specular_strength: 0.6,
+            opacity: 1.,
+            two_sided: false,
+        }
+    }
+}
+
+/// Tangent and bitangent of a triangle, pointing along increasing `u` and `v` respectively.
+pub fn tangent_frame(positions: &[Vec3d; 3], uvs: &[Vec2d; 3]) -> [Vec3d; 2] {
+    let edges: [Vec3d; 2] = [
+        sub(&positions[1], &positions[0]),
+        sub(&positions[2], &positions[0]),
+    ];
+    let [du1, dv1] = sub(&uvs[1], &uvs[0]);
+    let [du2, dv2] = sub(&uvs[2], &uvs[0]);
+
+    let determinant: f64 = du1 * dv2 - du2 * dv1;
+    if determinant == 0. {
+        return [[0.; 3]; 2];
+    }
+
+    [
+        scalar_mul(&weighted_sum(&edges, &[dv2, -dv1]), 1. / determinant),
+        scalar_mul(&weighted_sum(&edges, &[-du2, du1]), 1. / determinant),
+    ]
+}
+
+/// Shading normal of `fragment` after applying `map` if there is one, reversed if the fragment
+/// is on a back face and `two_sided`, so that both sides of a surface are lit.
+pub(crate) fn shading_normal(
+    map: Option<&NormalMap>,
+    fragment: &Fragment,
+    two_sided: bool,
+) -> Vec3d {
+    let normal: Vec3d = match map {
+        Some(map) => map.apply(fragment),
+        None => unit(&fragment.normal),
+    };
+
+    match two_sided && !fragment.front_facing {
+        true => scalar_mul(&normal, -1.),
+        false => normal,
+    }
+}
+
+/// Map a texel from `[0, 255]` to a direction in `[-1, 1]`.
+fn decode_direction(texel: &Vec3d) -> Vec3d {
+    texel.map(|channel| channel * 2. / 255. - 1.)
+}
+
+/// Sample `texture` at the fragment's texture coordinates, using its derivatives to filter.
+fn sample_rgba(texture: &Texture, fragment: &Fragment) -> Vec4d {
+    texture
+        .sample_grad(&fragment.uv, &fragment.duv[0], &fragment.duv[1])
+        .into()
+}
+
+/// Sample the colour of `texture`, ignoring its alpha.
+pub(crate) fn sample(texture: &Texture, fragment: &Fragment) -> Vec3d {
+    let [red, green, blue, _] = sample_rgba(texture, fragment);
+    [red, green, blue]
+}
+
+impl NormalMap {
+    /// Shading normal of the fragment after applying the map.
+    pub fn apply(&self, fragment: &Fragment) -> Vec3d {
+        let normal: Vec3d = unit(&fragment.normal);
+
+        match self {
+            NormalMap::Object(map) => unit(&decode_direction(&sample(map, fragment))),
+            NormalMap::Tangent(map) => {
+                let tangent: Vec3d = sub(
+                    &fragment.tangent,
+                    &scalar_mul(&normal, dot_product(&normal, &fragment.tangent)),
+                );
+                if length(&tangent) == 0. {
+                    return normal;
+                }
+
+                let tangent: Vec3d = unit(&tangent);
+                let mut bitangent: Vec3d = cross_product(&normal, &tangent);
+                if dot_product(&bitangent, &fragment.bitangent) < 0. {
+                    bitangent = scalar_mul(&bitangent, -1.);
+                }
+
+                let [x, y, z] = decode_direction(&sample(map, fragment));
+                unit(&weighted_sum(&[tangent, bitangent, normal], &[x, y, z]))
+            }
+        }
+    }
+}
+
+impl Material {
+    /// Shading normal of the fragment, after applying the normal map if there is one.
+    pub fn normal(&self, fragment: &Fragment) -> Vec3d {
+        shading_normal(self.normal.as_ref(), fragment, self.two_sided)
+    }
+
+    /// Albedo with its alpha, the Phong lighting factor under a directional light shining along
+    /// `-light` seen from along `view`, and the emitted colour, all in the texels' scale.
+    fn phong(
+        &self,
+        fragment: &Fragment,
+        light: &Vec3d,
+        view: &Vec3d,
+        visibility: f64,
+    ) -> (Vec4d, f64, Vec3d) {
+        let normal: Vec3d = self.normal(fragment);
+        let light: Vec3d = unit(light);
+
+        let albedo: Vec4d = match &self.diffuse {
+            Some(map) => sample_rgba(map, fragment),
+            None => [self.albedo[0], self.albedo[1], self.albedo[2], 255.],
+        };
+
+        let diffuse: f64 = dot_product(&normal, &light).max(0.);
+        let specular: f64 = match &self.specular {
+            Some(map) if diffuse > 0. => {
+                let exponent: f64 = sample(map, fragment)[0];
+                let reflected: Vec3d = sub(&scalar_mul(&normal, 2. * diffuse), &light);
+                dot_product(&reflected, &unit(view)).max(0.).powf(exponent)
+            }
+            _ => 0.,
+        };
+
+        let emissive: Vec3d = match &self.emissive {
+            Some(map) => sample(map, fragment),
+            None => [0.; 3],
+        };
+
+        let lighting: f64 =
+            self.ambient + visibility * (diffuse + self.specular_strength * specular);
+        (albedo, lighting, emissive)
+    }
+
+    /// Phong shading under a directional light shining along `-light`, seen from along `view`,
+    /// with `visibility` scaling the light's contribution.
+    pub fn shade(
+        &self,
+        fragment: &Fragment,
+        light: &Vec3d,
+        view: &Vec3d,
+        visibility: f64,
+    ) -> Pixel {
+        let ([red, green, blue, alpha], lighting, emissive) =
+            self.phong(fragment, light, view, visibility);
+
+        let [red, green, blue] = add(&mul(&[red, green, blue], &[lighting; 3]), &emissive);
+        [red, green, blue, alpha * self.opacity].into()
+    }
+
+    /// Unclamped linear radiance of the same shading, for rendering into an HDR target, with the
+    /// albedo and emissive colours decoded from sRGB.
+    pub fn radiance(
+        &self,
+        fragment: &Fragment,
+        light: &Vec3d,
+        view: &Vec3d,
+        visibility: f64,
+    ) -> Vec3d {
+        let ([red, green, blue, _], lighting, emissive) =
+            self.phong(fragment, light, view, visibility);
+        let linear = |colour: Vec3d| colour.map(|channel| srgb_to_linear(channel / 255.));
+
+        add(
+            &scalar_mul(&linear([red, green, blue]), lighting),
+            &linear(emissive),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::pixel;
+
+    fn flat(texel: Pixel) -> Texture {
+        Texture::new(1, 1, vec![texel])
+    }
+
+    fn fragment() -> Fragment {
+        Fragment {
+            position: [0.; 3],
+            normal: [0., 0., 1.],
+            uv: [0.5, 0.5],
+            duv: [[0.; 2]; 2],
+            tangent: [1., 0., 0.],
+            bitangent: [0., 1., 0.],
+            front_facing: true,
+        }
+    }
+
+    #[test]
+    fn test_tangent_frame() {
+        let positions: [Vec3d; 3] = [[0., 0., 0.], [2., 0., 0.], [0., 0., -2.]];
+        let uvs: [Vec2d; 3] = [[0., 0.], [1., 0.], [0., 1.]];
+
+        assert_eq!(
+            tangent_frame(&positions, &uvs),
+            [[2., 0., 0.], [0., 0., -2.]]
+        );
+    }
+
+    #[test]
+    fn tangent_space_normal_map() {
+        let tilted = Pixel {
+            red: 255,
+            green: 128,
+            blue: 128,
+            alpha: 255,
+        };
+        let material = Material {
+            normal: Some(NormalMap::Tangent(flat(tilted))),
+            ..Default::default()
+        };
+
+        let [x, y, z] = material.normal(&fragment());
+        assert!(x > 0.99 && y.abs() < 0.01 && z.abs() < 0.01);
+    }
+
+    #[test]
+    fn object_space_normal_map() {
+        let down = Pixel {
+            red: 128,
+            green: 0,
+            blue: 128,
+            alpha: 255,
+        };
+        let material = Material {
+            normal: Some(NormalMap::Object(flat(down))),
+            ..Default::default()
+        };
+
+        let [x, y, z] = material.normal(&fragment());
+        assert!(y < -0.99 && x.abs() < 0.01 && z.abs() < 0.01);
+    }
+
+    #[test]
+    fn two_sided_materials_light_back_faces() {
+        // A back face seen from behind its normal, lit from the viewer's side
+        let back = Fragment {
+            normal: [0., 0., -1.],
+            front_facing: false,
+            ..fragment()
+        };
+        let two_sided = Material {
+            two_sided: true,
+            ..Default::default()
+        };
+
+        assert_eq!(two_sided.normal(&back), [0., 0., 1.]);
+        assert_eq!(Material::default().normal(&back), [0., 0., -1.]);
+        let lit: Pixel = two_sided.shade(&back, &[0., 0., 1.], &[0., 0., 1.], 1.);
+        assert_eq!(lit, pixel::WHITE);
+
+        // Object-space maps ignore the interpolated normal, so their result is reversed instead
+        let away = Pixel {
+            red: 128,
+            green: 128,
+            blue: 0,
+            alpha: 255,
+        };
+        let mapped = Material {
+            normal: Some(NormalMap::Object(flat(away))),
+            two_sided: true,
+            ..Default::default()
+        };
+        let [x, y, z] = mapped.normal(&back);
+        assert!(z > 0.99 && x.abs() < 0.01 && y.abs() < 0.01);
+        let lit: Pixel = mapped.shade(&back, &[0., 0., 1.], &[0., 0., 1.], 1.);
+        assert_eq!(lit, pixel::WHITE);
+    }
+
+    #[test]
+    fn emissive_map_glows_in_shadow() {
+        let glow = Pixel {
+            red: 0,
+            green: 200,
+            blue: 0,
+            alpha: 255,
+        };
+        let material = Material {
+            albedo: [100.; 3],
+            emissive: Some(flat(glow)),
+            ..Default::default()
+        };
+
+        let shaded: Pixel = material.shade(&fragment(), &[0., 0., 1.], &[0., 0., 1.], 0.);
+        assert_eq!(
+            shaded,
+            Pixel {
+                red: 20,
+                green: 220,
+                blue: 20,
+                alpha: 255,
+            }
+        );
+    }
+
+    #[test]
+    fn opacity_scales_diffuse_alpha() {
+        let translucent = Material {
+            diffuse: Some(flat(Pixel {
+                red: 100,
+                green: 100,
+                blue: 100,
+                alpha: 200,
+            })),
+            opacity: 0.5,
+            ..Default::default()
+        };
+
+        let shaded: Pixel = translucent.shade(&fragment(), &[0., 0., 1.], &[0., 0., 1.], 1.);
+        assert_eq!(shaded.alpha, 100);
+        assert_eq!(
+            Material::default()
+                .shade(&fragment(), &[0., 0., 1.], &[0., 0., 1.], 1.)
+                .alpha,
+            255
+        );
+    }
+
+    #[test]
+    fn radiance_is_linear_and_unclamped() {
+        let material = Material {
+            ambient: 2.,
+            ..Default::default()
+        };
+
+        let radiance: Vec3d = material.radiance(&fragment(), &[0., 0., 1.], &[0., 0., 1.], 1.);
+        assert_eq!(radiance, [3.; 3]);
+        assert_eq!(
+            material.shade(&fragment(), &[0., 0., 1.], &[0., 0., 1.], 1.),
+            pixel::WHITE
+        );
+    }
+
+    #[test]
+    fn specular_map_sets_highlight_exponent() {
+        let shiny = Material {
+            specular: Some(flat(Pixel {
+                red: 10,
+                green: 10,
+                blue: 10,
+                alpha: 255,
+            })),
+            ..Default::default()
+        };
+        let light: Vec3d = [0., 1., 1.];
+
+        let matte: Pixel = Material::default().shade(&fragment(), &light, &[0., 0., 1.], 1.);
+        let glossy: Pixel = shiny.shade(&fragment(), &light, &[0., 0., 1.], 1.);
+        assert!(glossy.red > matte.red);
+    }
+}
@@ -0,0 +1,494 @@
+use std::f64::consts::PI;
+
+use crate::image::pixel::{Pixel, linear_to_srgb, srgb_to_linear};
+use crate::image::texture::{Filter, Texture};
+use crate::math::vector::{
+    Vec2d, Vec3d, add, dot_product, mul, scalar_mul, sub, unit, weighted_sum,
+};
+use crate::shading::{Fragment, NormalMap, sample, shading_normal};
+
+/// Resolution of the radiance map the environment is prefiltered from.
+const SOURCE_SIZE: (usize, usize) = (64, 32);
+/// Resolution of the irradiance and prefiltered specular maps.
+const FILTERED_SIZE: (usize, usize) = (32, 16);
+/// Number of prefiltered specular maps, spaced evenly in roughness from 0 to 1.
+const SPECULAR_LEVELS: usize = 5;
+
+/// Material input, either uniform across the surface or read from a texture.
+pub enum Input {
+    Constant(Vec3d),
+    Texture(Texture),
+}
+
+impl Input {
+    pub fn uniform(value: f64) -> Self {
+        Input::Constant([value; 3])
+    }
+
+    /// Value at the fragment, with texels scaled into `[0, 1]`.
+    fn sample(&self, fragment: &Fragment) -> Vec3d {
+        match self {
+            Input::Constant(value) => *value,
+            Input::Texture(texture) => scalar_mul(&sample(texture, fragment), 1. / 255.),
+        }
+    }
+}
+
+/// Metallic-roughness material, following the glTF convention of reading occlusion, roughness
+/// and metallic textures from the red, green and blue channels respectively.
+pub struct PbrMaterial {
+    /// Base colour, with textures decoded from sRGB.
+    pub albedo: Input,
+    pub metallic: Input,
+    pub roughness: Input,
+    pub occlusion: Input,
+    pub normal: Option<NormalMap>,
+    /// Light back faces as if they faced the viewer, by reversing their normals.
+    pub two_sided: bool,
+}
+
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            albedo: Input::uniform(1.),
+            metallic: Input::uniform(0.),
+            roughness: Input::uniform(0.5),
+            occlusion: Input::uniform(1.),
+            normal: None,
+            two_sided: false,
+        }
+    }
+}
+
+pub struct Light {
+    /// Direction towards the light.
+    pub direction: Vec3d,
+    /// Linear radiance arriving from the light.
+    pub colour: Vec3d,
+}
+
+fn distribution_ggx(n_dot_h: f64, roughness: f64) -> f64 {
+    let alpha_squared: f64 = roughness.powi(4);
+    let denominator: f64 = n_dot_h * n_dot_h * (alpha_squared - 1.) + 1.;
+    alpha_squared / (PI * denominator * denominator)
+}
+
+fn geometry_smith(n_dot_v: f64, n_dot_l: f64, roughness: f64) -> f64 {
+    let k: f64 = (roughness + 1.).powi(2) / 8.;
+    let schlick = |n_dot_x: f64| n_dot_x / (n_dot_x * (1. - k) + k);
+    schlick(n_dot_v) * schlick(n_dot_l)
+}
+
+fn fresnel_schlick(cos_theta: f64, f0: &Vec3d) -> Vec3d {
+    let factor: f64 = (1. - cos_theta).clamp(0., 1.).powi(5);
+    f0.map(|f| f + (1. - f) * factor)
+}
+
+/// Fresnel-Schlick with the grazing reflectance damped by roughness, for ambient lighting.
+fn fresnel_schlick_roughness(cos_theta: f64, f0: &Vec3d, roughness: f64) -> Vec3d {
+    let factor: f64 = (1. - cos_theta).clamp(0., 1.).powi(5);
+    f0.map(|f| f + ((1. - roughness).max(f) - f) * factor)
+}
+
+/// Scale and bias applied to `f0` by the split-sum specular integral, using Karis' analytic fit
+/// in place of a precomputed lookup table.
+fn environment_brdf(n_dot_v: f64, roughness: f64) -> Vec2d {
+    let r: [f64; 4] = [
+        1. - roughness,
+        roughness * -0.0275 + 0.0425,
+        roughness * -0.572 + 1.04,
+        roughness * 0.022 - 0.04,
+    ];
+    let a004: f64 = (r[0] * r[0]).min((-9.28 * n_dot_v).exp2()) * r[0] + r[1];
+    [-1.04 * a004 + r[2], 1.04 * a004 + r[3]]
+}
+
+fn reflect(incident: &Vec3d, normal: &Vec3d) -> Vec3d {
+    sub(
+        incident,
+        &scalar_mul(normal, 2. * dot_product(normal, incident)),
+    )
+}
+
+/// Texture coordinates of `direction` in an equirectangular map centred on `-z`.
+fn direction_to_uv(direction: &Vec3d) -> Vec2d {
+    let [x, y, z] = unit(direction);
+    [
+        0.5 + x.atan2(-z) / (2. * PI),
+        0.5 + y.clamp(-1., 1.).asin() / PI,
+    ]
+}
+
+fn uv_to_direction([u, v]: &Vec2d) -> Vec3d {
+    let longitude: f64 = (u - 0.5) * 2. * PI;
+    let latitude: f64 = (v - 0.5) * PI;
+    [
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    ]
+}
+
+/// Texture coordinates of the centre of texel `(x, y)`, counting rows from the top.
+fn texel_uv(x: usize, y: usize, width: usize, height: usize) -> Vec2d {
+    [
+        (x as f64 + 0.5) / width as f64,
+        1. - (y as f64 + 0.5) / height as f64,
+    ]
+}
+
+/// Equirectangular map of linear radiance, with rows ordered top to bottom.
+struct EquirectMap {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3d>,
+}
+
+impl EquirectMap {
+    fn from_fn<F>(width: usize, height: usize, mut radiance: F) -> Self
+    where
+        F: FnMut(&Vec2d, &Vec3d) -> Vec3d,
+    {
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv: Vec2d = texel_uv(x, y, width, height);
+                texels.push(radiance(&uv, &uv_to_direction(&uv)));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// Direction and solid angle of every texel.
+    fn texel_directions(&self) -> Vec<(Vec3d, f64)> {
+        let texel_size: f64 = (2. * PI / self.width as f64) * (PI / self.height as f64);
+        let mut directions = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let direction: Vec3d = uv_to_direction(&texel_uv(x, y, self.width, self.height));
+                directions.push((
+                    direction,
+                    texel_size * (1. - direction[1] * direction[1]).sqrt(),
+                ));
+            }
+        }
+        directions
+    }
+
+    /// Bilinear lookup, repeating horizontally and clamping vertically.
+    fn lookup(&self, direction: &Vec3d) -> Vec3d {
+        let [u, v] = direction_to_uv(direction);
+        let x: f64 = u * self.width as f64 - 0.5;
+        let y: f64 = (1. - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |dx: i64, dy: i64| -> Vec3d {
+            let column = (x0 as i64 + dx).rem_euclid(self.width as i64) as usize;
+            let row = (y0 as i64 + dy).clamp(0, self.height as i64 - 1) as usize;
+            self.texels[column + self.width * row]
+        };
+
+        weighted_sum(
+            &[texel(0, 0), texel(1, 0), texel(0, 1), texel(1, 1)],
+            &[
+                (1. - tx) * (1. - ty),
+                tx * (1. - ty),
+                (1. - tx) * ty,
+                tx * ty,
+            ],
+        )
+    }
+}
+
+/// Image-based lighting from an equirectangular environment map, prefiltered into a diffuse
+/// irradiance map and a set of specular maps of increasing roughness.
+pub struct Environment {
+    irradiance: EquirectMap,
+    specular: Vec<EquirectMap>,
+}
+
+impl Environment {
+    /// Prefilter an sRGB-encoded equirectangular `texture`, with its top row facing up.
+    pub fn new(mut texture: Texture) -> Self {
+        texture.filter = Filter::Trilinear;
+
+        let (width, height) = SOURCE_SIZE;
+        let footprint: [Vec2d; 2] = [[1. / width as f64, 0.], [0., 1. / height as f64]];
+        let source = EquirectMap::from_fn(width, height, |uv, _| {
+            let texel: Vec3d = texture.sample_grad(uv, &footprint[0], &footprint[1]).into();
+            texel.map(|channel| srgb_to_linear(channel / 255.))
+        });
+        let directions: Vec<(Vec3d, f64)> = source.texel_directions();
+
+        // Integrate radiance over the sphere, weighting each source texel by `weight`
+        let convolve = |normal: &Vec3d, weight: &dyn Fn(f64) -> f64| -> Vec3d {
+            let (total, weights) = std::iter::zip(&source.texels, &directions).fold(
+                ([0.; 3], 0.),
+                |(total, weights), (radiance, (direction, solid_angle))| {
+                    let w: f64 = weight(dot_product(normal, direction)) * solid_angle;
+                    (add(&total, &scalar_mul(radiance, w)), weights + w)
+                },
+            );
+            total.map(|channel| channel / weights.max(f64::MIN_POSITIVE))
+        };
+
+        let (width, height) = FILTERED_SIZE;
+        let irradiance = EquirectMap::from_fn(width, height, |_, normal| {
+            convolve(normal, &|cos_theta| cos_theta.max(0.))
+        });
+
+        let specular = (0..SPECULAR_LEVELS)
+            .map(|level| {
+                let roughness: f64 = level as f64 / (SPECULAR_LEVELS - 1) as f64;
+                if level == 0 {
+                    return EquirectMap::from_fn(width, height, |_, direction| {
+                        source.lookup(direction)
+                    });
+                }
+
+                // With the view along the normal, the half vector bisects normal and light
+                EquirectMap::from_fn(width, height, |_, normal| {
+                    convolve(normal, &|cos_theta| {
+                        let n_dot_h: f64 = ((1. + cos_theta) / 2.).max(0.).sqrt();
+                        distribution_ggx(n_dot_h, roughness) * cos_theta.max(0.)
+                    })
+                })
+            })
+            .collect();
+
+        Self {
+            irradiance,
+            specular,
+        }
+    }
+
+    /// Cosine-weighted radiance arriving around `normal`, divided by pi.
+    pub fn irradiance(&self, normal: &Vec3d) -> Vec3d {
+        self.irradiance.lookup(normal)
+    }
+
+    /// Radiance reflected along `direction` by a GGX lobe of the given roughness.
+    pub fn specular(&self, direction: &Vec3d, roughness: f64) -> Vec3d {
+        let level: f64 = roughness.clamp(0., 1.) * (SPECULAR_LEVELS - 1) as f64;
+        let (fine, coarse) = (level.floor() as usize, level.ceil() as usize);
+        let t: f64 = level - level.floor();
+
+        weighted_sum(
+            &[
+                self.specular[fine].lookup(direction),
+                self.specular[coarse].lookup(direction),
+            ],
+            &[1. - t, t],
+        )
+    }
+}
+
+impl PbrMaterial {
+    /// Linear radiance leaving the fragment towards `view`, lit by `light` scaled by
+    /// `visibility`, and by `environment` if there is one.
+    pub fn radiance(
+        &self,
+        fragment: &Fragment,
+        light: &Light,
+        view: &Vec3d,
+        visibility: f64,
+        environment: Option<&Environment>,
+    ) -> Vec3d {
+        let normal: Vec3d = shading_normal(self.normal.as_ref(), fragment, self.two_sided);
+        let view: Vec3d = unit(view);
+
+        let albedo: Vec3d = match &self.albedo {
+            Input::Constant(value) => *value,
+            texture => texture.sample(fragment).map(srgb_to_linear),
+        };
+        let metallic: f64 = self.metallic.sample(fragment)[2].clamp(0., 1.);
+        let roughness: f64 = self.roughness.sample(fragment)[1].clamp(0.04, 1.);
+        let occlusion: f64 = self.occlusion.sample(fragment)[0];
+
+        let f0: Vec3d = weighted_sum(&[[0.04; 3], albedo], &[1. - metallic, metallic]);
+        let n_dot_v: f64 = dot_product(&normal, &view).max(1e-4);
+
+        let light_direction: Vec3d = unit(&light.direction);
+        let n_dot_l: f64 = dot_product(&normal, &light_direction).max(0.);
+        let mut radiance: Vec3d = [0.; 3];
+
+        if n_dot_l > 0. && visibility > 0. {
+            let half: Vec3d = unit(&add(&view, &light_direction));
+            let fresnel: Vec3d = fresnel_schlick(dot_product(&half, &view).max(0.), &f0);
+            let specular: f64 = distribution_ggx(dot_product(&normal, &half).max(0.), roughness)
+                * geometry_smith(n_dot_v, n_dot_l, roughness)
+                / (4. * n_dot_v * n_dot_l);
+
+            // Light not reflected specularly is refracted, and absorbed entirely by metals
+            let diffuse: Vec3d = mul(
+                &fresnel.map(|f| (1. - f) * (1. - metallic)),
+                &scalar_mul(&albedo, 1. / PI),
+            );
+            let brdf: Vec3d = add(&diffuse, &scalar_mul(&fresnel, specular));
+
+            radiance = scalar_mul(&mul(&brdf, &light.colour), n_dot_l * visibility);
+        }
+
+        if let Some(environment) = environment {
+            let fresnel: Vec3d = fresnel_schlick_roughness(n_dot_v, &f0, roughness);
+            let diffuse: Vec3d = mul(
+                &fresnel.map(|f| (1. - f) * (1. - metallic)),
+                &mul(&albedo, &environment.irradiance(&normal)),
+            );
+
+            let [scale, bias] = environment_brdf(n_dot_v, roughness);
+            let reflected: Vec3d = reflect(&scalar_mul(&view, -1.), &normal);
+            let specular: Vec3d = mul(
+                &environment.specular(&reflected, roughness),
+                &f0.map(|f| f * scale + bias),
+            );
+
+            radiance = add(&radiance, &scalar_mul(&add(&diffuse, &specular), occlusion));
+        }
+
+        radiance
+    }
+
+    /// Radiance of the fragment, clamped and encoded with the sRGB transfer function.
+    pub fn shade(
+        &self,
+        fragment: &Fragment,
+        light: &Light,
+        view: &Vec3d,
+        visibility: f64,
+        environment: Option<&Environment>,
+    ) -> Pixel {
+        self.radiance(fragment, light, view, visibility, environment)
+            .map(|channel| linear_to_srgb(channel.clamp(0., 1.)) * 255.)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment() -> Fragment {
+        Fragment {
+            position: [0.; 3],
+            normal: [0., 0., 1.],
+            uv: [0.5, 0.5],
+            duv: [[0.; 2]; 2],
+            tangent: [1., 0., 0.],
+            bitangent: [0., 1., 0.],
+            front_facing: true,
+        }
+    }
+
+    fn uniform_environment(value: u8) -> Environment {
+        let grey = Pixel {
+            red: value,
+            green: value,
+            blue: value,
+            alpha: 255,
+        };
+        Environment::new(Texture::new(8, 4, vec![grey; 32]))
+    }
+
+    #[test]
+    fn equirect_directions_round_trip() {
+        for uv in [[0.5, 0.5], [0.25, 0.75], [0.9, 0.1]] {
+            let [u, v] = direction_to_uv(&uv_to_direction(&uv));
+            assert!((u - uv[0]).abs() < 1e-9 && (v - uv[1]).abs() < 1e-9);
+        }
+        assert_eq!(uv_to_direction(&[0.5, 1.]).map(|c| c.round()), [0., 1., 0.]);
+    }
+
+    #[test]
+    fn ggx_distribution_is_normalised() {
+        // The projected microfacet area must integrate to one over the hemisphere
+        let steps = 2000;
+        for roughness in [0.3, 0.6, 1.] {
+            let integral: f64 = (0..steps)
+                .map(|i| {
+                    let theta: f64 = (i as f64 + 0.5) / steps as f64 * PI / 2.;
+                    let n_dot_h: f64 = theta.cos();
+                    distribution_ggx(n_dot_h, roughness) * n_dot_h * theta.sin() * 2. * PI
+                })
+                .sum::<f64>()
+                * (PI / 2.)
+                / steps as f64;
+            assert!((integral - 1.).abs() < 1e-2, "{roughness}: {integral}");
+        }
+    }
+
+    #[test]
+    fn lambertian_surface_under_uniform_environment() {
+        let environment = uniform_environment(255);
+        let material = PbrMaterial {
+            roughness: Input::uniform(1.),
+            ..Default::default()
+        };
+        let dark = Light {
+            direction: [0., 0., 1.],
+            colour: [0.; 3],
+        };
+
+        // A white furnace: diffuse and specular together should return roughly the incoming light
+        let [r, g, b] =
+            material.radiance(&fragment(), &dark, &[0., 0., 1.], 1., Some(&environment));
+        assert!((r - 1.).abs() < 0.1 && r == g && g == b, "{r}");
+    }
+
+    #[test]
+    fn metals_have_no_diffuse_term() {
+        let light = Light {
+            direction: [0., 1., 1.],
+            colour: [1.; 3],
+        };
+        let metal = PbrMaterial {
+            metallic: Input::uniform(1.),
+            roughness: Input::uniform(0.2),
+            albedo: Input::Constant([1., 0., 0.]),
+            ..Default::default()
+        };
+
+        let [r, g, b] = metal.radiance(&fragment(), &light, &[0., -1., 1.], 1., None);
+        assert!(r > 10. * g && g == b, "{r} {g}");
+
+        let [r, _, _] = metal.radiance(&fragment(), &light, &[0., 0., 1.], 1., None);
+        assert!(r < 0.05, "{r}");
+    }
+
+    #[test]
+    fn occlusion_and_visibility_darken() {
+        let environment = uniform_environment(128);
+        let light = Light {
+            direction: [0., 0., 1.],
+            colour: [1.; 3],
+        };
+        let lit = PbrMaterial::default().shade(
+            &fragment(),
+            &light,
+            &[0., 0., 1.],
+            1.,
+            Some(&environment),
+        );
+        let shadowed = PbrMaterial::default().shade(
+            &fragment(),
+            &light,
+            &[0., 0., 1.],
+            0.,
+            Some(&environment),
+        );
+        let occluded = PbrMaterial {
+            occlusion: Input::uniform(0.),
+            ..Default::default()
+        }
+        .shade(&fragment(), &light, &[0., 0., 1.], 0., Some(&environment));
+
+        assert!(lit.red > shadowed.red && shadowed.red > occluded.red);
+        assert_eq!(occluded.red, 0);
+    }
+}
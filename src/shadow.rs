@@ -0,0 +1,133 @@
+use crate::geometry::{Geometry, Vertex};
+use crate::image::pixel::Pixel;
+use crate::image::{Image, Position};
+use crate::math::matrix::Matrix4d;
+use crate::math::transform::apply;
+
+/// Depth of a scene as seen from a light, used to test whether points are lit.
+pub struct ShadowMap {
+    depth: Image,
+    light: Matrix4d,
+    /// Depth buffer distance that a point may sit behind the stored depth and still be lit.
+    pub bias: f64,
+    /// Half-width of the square of texels averaged by percentage-closer filtering.
+    pub pcf_radius: usize,
+}
+
+impl ShadowMap {
+    /// Render the depth of `geometry` after transforming it by the `light` view matrix.
+    pub fn render(geometry: &Geometry, light: Matrix4d, width: usize, height: usize) -> Self {
+        let mut depth: Image = Image::blank(width, height);
+        let vertices: Vec<Vertex> = geometry
+            .vertices
+            .iter()
+            .map(|&v| apply(&light, &v.into()).into())
+            .collect();
+
+        for face in &geometry.faces {
+            depth.shaded_triangle(
+                &vertices[face.0],
+                &vertices[face.1],
+                &vertices[face.2],
+                |_| Pixel {
+                    red: 0,
+                    green: 0,
+                    blue: 0,
+                    alpha: 255,
+                },
+            );
+        }
+
+        Self {
+            depth,
+            light,
+            bias: 0.005,
+            pcf_radius: 1,
+        }
+    }
+
+    /// Fraction of the shadow map texels around `point` that do not occlude it from the light.
+    pub fn visibility(&self, point: &Vertex) -> f64 {
+        let projected: Vertex = apply(&self.light, &(*point).into()).into();
+        let (centre, depth): (Position, f64) = self.depth.project_vertex(&projected);
+
+        let radius = self.pcf_radius as i64;
+        let mut lit: usize = 0;
+        let mut samples: usize = 0;
+
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                samples += 1;
+
+                let (Ok(x), Ok(y)) = (
+                    usize::try_from(centre.x as i64 + dx),
+                    usize::try_from(centre.y as i64 + dy),
+                ) else {
+                    lit += 1;
+                    continue;
+                };
+
+                if depth - self.bias <= self.depth.depth(&Position { x, y }) {
+                    lit += 1;
+                }
+            }
+        }
+
+        lit as f64 / samples as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Triangle;
+    use crate::math::transform::look_at;
+
+    fn occluded_scene() -> Geometry {
+        let quad = |z: f64| {
+            [
+                [-0.5, -0.5, z],
+                [0.5, -0.5, z],
+                [0.5, 0.5, z],
+                [-0.5, 0.5, z],
+            ]
+            .map(Vertex::from)
+        };
+
+        Geometry {
+            vertices: [quad(0.5), quad(-0.5)].concat(),
+            faces: vec![
+                Triangle(0, 1, 2),
+                Triangle(0, 2, 3),
+                Triangle(4, 5, 6),
+                Triangle(4, 6, 7),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn occluded_points_are_shadowed() {
+        let light: Matrix4d = look_at(&[0., 0., 1.], &[0., 0., 0.], &[0., 1., 0.]);
+        let shadow = ShadowMap::render(&occluded_scene(), light, 64, 64);
+
+        let front: Vertex = [0., 0., 0.5].into();
+        let back: Vertex = [0., 0., -0.5].into();
+        let outside: Vertex = [0.9, 0.9, -0.5].into();
+
+        assert_eq!(shadow.visibility(&front), 1.);
+        assert_eq!(shadow.visibility(&back), 0.);
+        assert_eq!(shadow.visibility(&outside), 1.);
+    }
+
+    #[test]
+    fn pcf_softens_shadow_edges() {
+        let light: Matrix4d = look_at(&[0., 0., 1.], &[0., 0., 0.], &[0., 1., 0.]);
+        let mut shadow = ShadowMap::render(&occluded_scene(), light, 64, 64);
+        let edge: Vertex = [0.5, 0., -0.5].into();
+
+        shadow.pcf_radius = 2;
+        let visibility: f64 = shadow.visibility(&edge);
+        assert!(0. < visibility && visibility < 1., "{visibility}");
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use crate::geometry::{Geometry, Triangle};
+use crate::image::Image;
+use crate::image::pixel::{Pixel, WHITE};
+use crate::math::vector::{Vec3d, cross_product, dot_product, sub, unit};
+
+/// What is drawn for each face of a mesh.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Shaded faces only.
+    Filled,
+    /// Every selected edge, whether or not it is hidden.
+    Wireframe,
+    /// Selected edges over the shaded faces, except where nearer faces hide them.
+    Overlay,
+    /// Selected edges only, except where faces nearer the viewer hide them.
+    HiddenLine,
+}
+
+/// Which edges of a mesh are drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Edges {
+    All,
+    /// Silhouettes, boundaries and creases whose faces meet at more than `crease_angle` radians.
+    Features {
+        crease_angle: f64,
+    },
+}
+
+/// Kind of feature an edge belongs to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feature {
+    /// Edge of only one face, or of more than two.
+    Boundary,
+    /// Edge between a face turned towards the viewer and one turned away.
+    Silhouette,
+    Crease,
+}
+
+/// Edge between two vertices, shared by the faces listed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub start: usize,
+    pub end: usize,
+    pub faces: Vec<usize>,
+}
+
+/// Edge lines drawn by `render`.
+pub struct Wireframe {
+    pub colour: Pixel,
+    pub edges: Edges,
+    /// Distance in projected `z` that edges are moved towards the viewer before the depth test.
+    pub bias: f64,
+}
+
+/// Every distinct edge of the faces of `geometry`, ordered by their vertices.
+pub fn edges(geometry: &Geometry) -> Vec<Edge> {
+    let mut edges: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+
+    for (f, face) in geometry.faces.iter().enumerate() {
+        for (a, b) in [(face.0, face.1), (face.1, face.2), (face.2, face.0)] {
+            edges.entry((a.min(b), a.max(b))).or_default().push(f);
+        }
+    }
+
+    edges
+        .into_iter()
+        .map(|((start, end), faces)| Edge { start, end, faces })
+        .collect()
+}
+
+fn face_normal(geometry: &Geometry, face: &Triangle) -> Vec3d {
+    let [a, b, c]: [Vec3d; 3] = [face.0, face.1, face.2].map(|v| geometry.vertices[v].into());
+    cross_product(&sub(&b, &a), &sub(&c, &a))
+}
+
+/// The feature `edge` belongs to, with faces turned towards the viewer when they wind
+/// anticlockwise on screen, the default front-face winding.
+pub fn feature(geometry: &Geometry, edge: &Edge, crease_angle: f64) -> Option<Feature> {
+    let [first, second] = edge.faces[..] else {
+        return Some(Feature::Boundary);
+    };
+    let [n, m] = [first, second].map(|f| face_normal(geometry, &geometry.faces[f]));
+
+    if (n[2] > 0.) != (m[2] > 0.) {
+        return Some(Feature::Silhouette);
+    }
+    let cosine: f64 = dot_product(&unit(&n), &unit(&m)).clamp(-1., 1.);
+    (cosine.acos() > crease_angle).then_some(Feature::Crease)
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Self {
+            colour: WHITE,
+            edges: Edges::All,
+            bias: 0.001,
+        }
+    }
+}
+
+impl Wireframe {
+    /// Draw the selected edges of `geometry`, hiding those behind the depth buffer if `hidden`.
+    pub fn draw(&self, image: &mut Image, geometry: &Geometry, hidden: bool) {
+        for edge in edges(geometry) {
+            if let Edges::Features { crease_angle } = self.edges
+                && feature(geometry, &edge, crease_angle).is_none()
+            {
+                continue;
+            }
+
+            let (i, j) = (&geometry.vertices[edge.start], &geometry.vertices[edge.end]);
+            image.edge(self.colour, i, j, hidden.then_some(self.bias));
+        }
+    }
+}
+
+/// Draw `geometry` in `mode`, calling `shade` with the indices of the faces to fill, from the
+/// back.
+pub fn render<F>(
+    image: &mut Image,
+    geometry: &Geometry,
+    mode: Mode,
+    wireframe: &Wireframe,
+    shade: F,
+) where
+    F: FnOnce(&mut Image, &[usize]),
+{
+    match mode {
+        Mode::Filled | Mode::Overlay => shade(image, &geometry.back_to_front()),
+        Mode::HiddenLine => {
+            for face in &geometry.faces {
+                let [i, j, k] = [face.0, face.1, face.2].map(|v| &geometry.vertices[v]);
+                image.depth_triangle(i, j, k);
+            }
+        }
+        Mode::Wireframe => {}
+    }
+
+    // Multisampled faces only reach the depth buffer once resolved
+    image.resolve();
+    match mode {
+        Mode::Filled => {}
+        Mode::Wireframe => wireframe.draw(image, geometry, false),
+        Mode::Overlay | Mode::HiddenLine => wireframe.draw(image, geometry, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vertex;
+    use crate::image::Position;
+
+    fn geometry(vertices: &[Vec3d], faces: &[(usize, usize, usize)]) -> Geometry {
+        Geometry {
+            vertices: vertices.iter().map(|&v| Vertex::from(v)).collect(),
+            faces: faces.iter().map(|&(a, b, c)| Triangle(a, b, c)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Square split along a diagonal, with its far corner at `z` raised or lowered.
+    fn square(z: f64) -> Geometry {
+        geometry(
+            &[[-1., -1., 0.], [1., -1., 0.], [1., 1., z], [-1., 1., 0.]],
+            &[(0, 1, 3), (1, 2, 3)],
+        )
+    }
+
+    #[test]
+    fn shared_edges_are_listed_once() {
+        let edges: Vec<Edge> = edges(&square(0.));
+
+        assert_eq!(edges.len(), 5);
+        let diagonal: &Edge = edges.iter().find(|e| (e.start, e.end) == (1, 3)).unwrap();
+        assert_eq!(diagonal.faces, [0, 1]);
+    }
+
+    #[test]
+    fn feature_edges() {
+        let diagonal = Edge {
+            start: 1,
+            end: 3,
+            faces: vec![0, 1],
+        };
+        let crease_angle: f64 = 0.5;
+
+        assert_eq!(feature(&square(0.), &diagonal, crease_angle), None);
+        assert_eq!(
+            feature(&square(2.), &diagonal, crease_angle),
+            Some(Feature::Crease)
+        );
+
+        // Folding the second face right over turns it away from the viewer
+        let folded = geometry(
+            &[[-1., -1., 0.], [1., -1., 0.], [-1., -1., 1.], [-1., 1., 0.]],
+            &[(0, 1, 3), (1, 2, 3)],
+        );
+        assert_eq!(
+            feature(&folded, &diagonal, crease_angle),
+            Some(Feature::Silhouette)
+        );
+
+        let boundary: Edge = edges(&square(0.)).remove(0);
+        assert_eq!(
+            feature(&square(0.), &boundary, crease_angle),
+            Some(Feature::Boundary)
+        );
+    }
+
+    #[test]
+    fn hidden_lines_are_suppressed() {
+        // A small triangle behind a square covering the whole image
+        let mut scene = square(0.);
+        scene
+            .vertices
+            .extend([[-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [0., 0.5, -0.5]].map(Vertex::from));
+        scene.faces.push(Triangle(4, 5, 6));
+
+        let wireframe = Wireframe::default();
+        let drawn = |mode: Mode| {
+            let mut image = Image::blank(16, 16);
+            render(&mut image, &scene, mode, &wireframe, |_, _| {});
+            // A point on the bottom edge of the hidden triangle, and one on the square's border
+            let hidden = image.get(&Position { x: 8, y: 4 }) == Some(wireframe.colour);
+            let border = image.get(&Position { x: 8, y: 0 }) == Some(wireframe.colour);
+            (hidden, border)
+        };
+
+        assert_eq!(drawn(Mode::Wireframe), (true, true));
+        assert_eq!(drawn(Mode::HiddenLine), (false, true));
+        assert_eq!(drawn(Mode::Filled), (false, false));
+    }
+}